@@ -1,4 +1,18 @@
 fn main() {
-    // Describe how to build C files for tests
-    cc::Build::new().file("tests/gen.c").compile("gen");
+    // The scenario generator is part of the library
+    cc::Build::new().file("src/scenario/gen.c").compile("gen");
+
+    // The C test of the interface is only linked into the test binaries, so that the
+    // library does not export it
+    let objects = cc::Build::new()
+        .file("tests/capi.c")
+        .include("include")
+        .compile_intermediates();
+    for object in objects {
+        println!("cargo:rustc-link-arg-tests={}", object.display());
+    }
+
+    for path in ["src/scenario/gen.c", "tests/capi.c", "include/vt_vm.h"] {
+        println!("cargo:rerun-if-changed={}", path);
+    }
 }
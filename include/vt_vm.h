@@ -0,0 +1,253 @@
+#ifndef VT_VM_H
+#define VT_VM_H
+
+/* Generated with cbindgen from src/capi.rs, do not edit. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * Result of a call to the API.
+ */
+enum {
+  VT_STATUS_OK = 0,
+  /**
+   * The run stopped because its fuel was exhausted, it can be resumed.
+   */
+  VT_STATUS_OUT_OF_FUEL = 1,
+  /**
+   * The run was interrupted, it can be resumed.
+   */
+  VT_STATUS_INTERRUPTED = 2,
+  /**
+   * A required pointer argument is null.
+   */
+  VT_STATUS_NULL_POINTER = 10,
+  /**
+   * An argument is out of its range, such as an unknown running mode.
+   */
+  VT_STATUS_INVALID_ARGUMENT = 11,
+  /**
+   * The library panicked, this is a bug.
+   */
+  VT_STATUS_PANIC = 12,
+  /**
+   * See `VmError::InvalidOpcode` and `ProgramLoadError::InvalidOpcode`.
+   */
+  VT_STATUS_INVALID_OPCODE = 20,
+  VT_STATUS_IP_OUT_OF_BOUNDS = 21,
+  VT_STATUS_IP_UNDERFLOW = 22,
+  VT_STATUS_ARITHMETIC_OVERFLOW = 23,
+  VT_STATUS_JIT_VERIFICATION = 24,
+  VT_STATUS_JIT_COMPILATION = 25,
+  /**
+   * See `ProgramLoadError`.
+   */
+  VT_STATUS_TRUNCATED_HEADER = 30,
+  VT_STATUS_IO = 31,
+  VT_STATUS_NON_UTF8_PATH = 32,
+  VT_STATUS_UNSUPPORTED_VERSION = 33,
+  VT_STATUS_UNSUPPORTED_FLAGS = 34,
+  VT_STATUS_CHECKSUM_MISMATCH = 35,
+  VT_STATUS_TRUNCATED_SECTION = 36,
+  VT_STATUS_INVALID_SECTION = 37,
+};
+typedef uint32_t VtStatus;
+
+/**
+ * Values of the `mode` argument of [`vt_vm_new`].
+ */
+enum {
+  VT_RUNNING_MODE_SIMPLE = 0,
+  VT_RUNNING_MODE_NO_OPT_JITTED = 1,
+  VT_RUNNING_MODE_OPT_JITTED = 2,
+  VT_RUNNING_MODE_DECODED = 3,
+  VT_RUNNING_MODE_THREADED = 4,
+  VT_RUNNING_MODE_CLOSURES = 5,
+  VT_RUNNING_MODE_SUPERINSTRUCTIONS = 6,
+  VT_RUNNING_MODE_REPLICATED = 7,
+  VT_RUNNING_MODE_TEMPLATE_JIT = 8,
+  VT_RUNNING_MODE_NO_OPT_CRANELIFT = 9,
+  VT_RUNNING_MODE_OPT_CRANELIFT = 10,
+};
+typedef uint32_t VtRunningMode;
+
+/**
+ * A program loaded by the library.
+ */
+typedef struct VtProgram VtProgram;
+
+/**
+ * A VM created by [`vt_vm_new`].
+ */
+typedef struct VtVm VtVm;
+
+/**
+ * Report of the last run of a VM, see `ExecutionReport`.
+ */
+typedef struct {
+  uint32_t ip;
+  int32_t acc;
+  int32_t lc;
+  bool halted;
+  uint64_t instructions;
+  uint64_t back_edges_taken;
+  uint64_t back_edges_not_taken;
+  uint64_t dispatches_saved;
+  uint64_t compile_ns;
+  uint64_t verify_ns;
+  uint64_t execute_ns;
+} VtReport;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Message of the last error raised on the calling thread, or null if the last call
+ * succeeded. The string is owned by the library and valid until the next call.
+ */
+const char *vt_last_error_message(void);
+
+/**
+ * Creates a program from the raw bytecode `code`, booting with `acc` and `lc`.
+ *
+ * # Safety
+ *
+ * `code` must point to `len` readable bytes, it may be null if `len` is 0.
+ */
+VtStatus vt_program_new(const uint8_t *code,
+                        size_t len,
+                        int32_t acc,
+                        int32_t lc,
+                        VtProgram **program);
+
+/**
+ * Loads a program from the content of a program file, in the legacy or the
+ * container format.
+ *
+ * # Safety
+ *
+ * `bytes` must point to `len` readable bytes, it may be null if `len` is 0.
+ */
+VtStatus vt_program_load(const uint8_t *bytes, size_t len, VtProgram **program);
+
+/**
+ * Loads a program from the file at `path`.
+ *
+ * # Safety
+ *
+ * `path` must be a NUL terminated string.
+ */
+VtStatus vt_program_load_file(const char *path, VtProgram **program);
+
+/**
+ * Releases a program. VMs created from it remain valid.
+ *
+ * # Safety
+ *
+ * `program` must be null or returned by this library, and not freed yet.
+ */
+void vt_program_free(VtProgram *program);
+
+/**
+ * Creates a VM running a copy of `program` in `mode`, one of [`VtRunningMode`].
+ *
+ * # Safety
+ *
+ * `program` must be null or a live program returned by this library.
+ */
+VtStatus vt_vm_new(const VtProgram *program, uint32_t mode, VtVm **vm);
+
+/**
+ * Runs the VM until it halts. Returns [`VtStatus::Ok`] once it halted, or the
+ * status of the error which stopped it.
+ *
+ * # Safety
+ *
+ * `vm` must be null or a live VM returned by this library.
+ */
+VtStatus vt_vm_run(VtVm *vm);
+
+/**
+ * Runs the VM for at most `fuel` instructions, returning [`VtStatus::OutOfFuel`]
+ * if it did not halt. Running it again resumes the execution.
+ *
+ * # Safety
+ *
+ * `vm` must be null or a live VM returned by this library.
+ */
+VtStatus vt_vm_run_with_fuel(VtVm *vm, uint64_t fuel);
+
+/**
+ * Restores the boot registers of the VM.
+ *
+ * # Safety
+ *
+ * `vm` must be null or a live VM returned by this library.
+ */
+VtStatus vt_vm_reset(VtVm *vm);
+
+/**
+ * Instruction pointer of the VM, 0 if `vm` is null.
+ *
+ * # Safety
+ *
+ * `vm` must be null or a live VM returned by this library.
+ */
+uint32_t vt_vm_ip(const VtVm *vm);
+
+/**
+ * Accumulator of the VM, 0 if `vm` is null.
+ *
+ * # Safety
+ *
+ * `vm` must be null or a live VM returned by this library.
+ */
+int32_t vt_vm_acc(const VtVm *vm);
+
+/**
+ * Loop counter of the VM, 0 if `vm` is null.
+ *
+ * # Safety
+ *
+ * `vm` must be null or a live VM returned by this library.
+ */
+int32_t vt_vm_lc(const VtVm *vm);
+
+/**
+ * True if the VM executed a HALT, false if `vm` is null.
+ *
+ * # Safety
+ *
+ * `vm` must be null or a live VM returned by this library.
+ */
+bool vt_vm_is_halted(const VtVm *vm);
+
+/**
+ * Writes the report of the last run of the VM to `report`.
+ *
+ * # Safety
+ *
+ * `vm` must be null or a live VM returned by this library, `report` must be null
+ * or writable.
+ */
+VtStatus vt_vm_report(const VtVm *vm, VtReport *report);
+
+/**
+ * Releases a VM.
+ *
+ * # Safety
+ *
+ * `vm` must be null or returned by this library, and not freed yet.
+ */
+void vt_vm_free(VtVm *vm);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* VT_VM_H */
@@ -0,0 +1,458 @@
+//! C interface of the VM, declared in `include/vt_vm.h`.
+//!
+//! Programs and VMs are opaque handles allocated by the library and released with
+//! [`vt_program_free`] and [`vt_vm_free`]. Fallible functions return a [`VtStatus`]
+//! and write their result through an out pointer. When a function fails, the
+//! message of the Rust error is kept for the calling thread and can be read with
+//! [`vt_last_error_message`].
+//!
+//! Regenerate the header after changing this file:
+//!
+//! ```text
+//! cbindgen --config cbindgen.toml --output include/vt_vm.h
+//! ```
+
+use std::{
+    cell::RefCell,
+    ffi::{c_char, CStr, CString},
+    panic::{self, AssertUnwindSafe},
+    ptr, slice,
+};
+
+use crate::vm::{
+    error::{ProgramLoadError, VmError},
+    program::Program,
+    report::ExecutionReport,
+    ExecutionOutcome, RunningMode, VM,
+};
+
+/// Result of a call to the API.
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VtStatus {
+    Ok = 0,
+    /// The run stopped because its fuel was exhausted, it can be resumed.
+    OutOfFuel = 1,
+    /// The run was interrupted, it can be resumed.
+    Interrupted = 2,
+    /// A required pointer argument is null.
+    NullPointer = 10,
+    /// An argument is out of its range, such as an unknown running mode.
+    InvalidArgument = 11,
+    /// The library panicked, this is a bug.
+    Panic = 12,
+    /// See `VmError::InvalidOpcode` and `ProgramLoadError::InvalidOpcode`.
+    InvalidOpcode = 20,
+    IpOutOfBounds = 21,
+    IpUnderflow = 22,
+    ArithmeticOverflow = 23,
+    JitVerification = 24,
+    JitCompilation = 25,
+    /// See `ProgramLoadError`.
+    TruncatedHeader = 30,
+    Io = 31,
+    NonUtf8Path = 32,
+    UnsupportedVersion = 33,
+    UnsupportedFlags = 34,
+    ChecksumMismatch = 35,
+    TruncatedSection = 36,
+    InvalidSection = 37,
+}
+
+impl From<&VmError> for VtStatus {
+    fn from(e: &VmError) -> Self {
+        match e {
+            VmError::InvalidOpcode { .. } => VtStatus::InvalidOpcode,
+            VmError::IpOutOfBounds { .. } => VtStatus::IpOutOfBounds,
+            VmError::IpUnderflow { .. } => VtStatus::IpUnderflow,
+            VmError::ArithmeticOverflow { .. } => VtStatus::ArithmeticOverflow,
+            VmError::JitVerification { .. } => VtStatus::JitVerification,
+            VmError::JitCompilation { .. } => VtStatus::JitCompilation,
+        }
+    }
+}
+
+impl From<&ProgramLoadError> for VtStatus {
+    fn from(e: &ProgramLoadError) -> Self {
+        match e {
+            ProgramLoadError::TruncatedHeader { .. } => VtStatus::TruncatedHeader,
+            ProgramLoadError::Io(_) => VtStatus::Io,
+            ProgramLoadError::NonUtf8Path(_) => VtStatus::NonUtf8Path,
+            ProgramLoadError::InvalidOpcode { .. } => VtStatus::InvalidOpcode,
+            ProgramLoadError::UnsupportedVersion { .. } => VtStatus::UnsupportedVersion,
+            ProgramLoadError::UnsupportedFlags { .. } => VtStatus::UnsupportedFlags,
+            ProgramLoadError::ChecksumMismatch { .. } => VtStatus::ChecksumMismatch,
+            ProgramLoadError::TruncatedSection { .. } => VtStatus::TruncatedSection,
+            ProgramLoadError::InvalidSection { .. } => VtStatus::InvalidSection,
+        }
+    }
+}
+
+/// Values of the `mode` argument of [`vt_vm_new`].
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VtRunningMode {
+    Simple = 0,
+    NoOptJitted = 1,
+    OptJitted = 2,
+    Decoded = 3,
+    Threaded = 4,
+    Closures = 5,
+    Superinstructions = 6,
+    Replicated = 7,
+    TemplateJit = 8,
+    NoOptCranelift = 9,
+    OptCranelift = 10,
+}
+
+impl VtRunningMode {
+    fn from_raw(mode: u32) -> Option<RunningMode> {
+        match mode {
+            m if m == VtRunningMode::Simple as u32 => Some(RunningMode::Simple),
+            m if m == VtRunningMode::NoOptJitted as u32 => Some(RunningMode::NoOptJitted),
+            m if m == VtRunningMode::OptJitted as u32 => Some(RunningMode::OptJitted),
+            m if m == VtRunningMode::Decoded as u32 => Some(RunningMode::Decoded),
+            m if m == VtRunningMode::Threaded as u32 => Some(RunningMode::Threaded),
+            m if m == VtRunningMode::Closures as u32 => Some(RunningMode::Closures),
+            m if m == VtRunningMode::Superinstructions as u32 => Some(RunningMode::Superinstructions),
+            m if m == VtRunningMode::Replicated as u32 => Some(RunningMode::Replicated),
+            m if m == VtRunningMode::TemplateJit as u32 => Some(RunningMode::TemplateJit),
+            m if m == VtRunningMode::NoOptCranelift as u32 => Some(RunningMode::NoOptCranelift),
+            m if m == VtRunningMode::OptCranelift as u32 => Some(RunningMode::OptCranelift),
+            _ => None,
+        }
+    }
+}
+
+/// Report of the last run of a VM, see `ExecutionReport`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct VtReport {
+    pub ip: u32,
+    pub acc: i32,
+    pub lc: i32,
+    pub halted: bool,
+    pub instructions: u64,
+    pub back_edges_taken: u64,
+    pub back_edges_not_taken: u64,
+    pub dispatches_saved: u64,
+    pub compile_ns: u64,
+    pub verify_ns: u64,
+    pub execute_ns: u64,
+}
+
+impl From<ExecutionReport> for VtReport {
+    fn from(report: ExecutionReport) -> Self {
+        let nanos = |duration: std::time::Duration| u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX);
+        Self {
+            ip: report.ip,
+            acc: report.acc,
+            lc: report.lc,
+            halted: report.halted,
+            instructions: report.instructions,
+            back_edges_taken: report.back_edges_taken,
+            back_edges_not_taken: report.back_edges_not_taken,
+            dispatches_saved: report.dispatches_saved,
+            compile_ns: nanos(report.compile_time),
+            verify_ns: nanos(report.verify_time),
+            execute_ns: nanos(report.execute_time),
+        }
+    }
+}
+
+/// A program loaded by the library.
+pub struct VtProgram(Program);
+
+/// A VM created by [`vt_vm_new`].
+pub struct VtVm(VM);
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: String) {
+    // Interior NUL bytes cannot be represented, drop them
+    let message = CString::new(message.replace('\0', "")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+/// Runs `f`, keeping the message of the error it returns. Panics must not unwind
+/// into C code, they are reported as [`VtStatus::Panic`].
+fn guard<F: FnOnce() -> Result<VtStatus, (VtStatus, String)>>(f: F) -> VtStatus {
+    LAST_ERROR.with(|last| *last.borrow_mut() = None);
+
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(status)) => status,
+        Ok(Err((status, message))) => {
+            set_last_error(message);
+            status
+        }
+        Err(_) => {
+            set_last_error("the library panicked".to_string());
+            VtStatus::Panic
+        }
+    }
+}
+
+fn null_pointer(argument: &str) -> (VtStatus, String) {
+    (VtStatus::NullPointer, format!("`{}` is null", argument))
+}
+
+fn load_error(e: ProgramLoadError) -> (VtStatus, String) {
+    (VtStatus::from(&e), e.to_string())
+}
+
+fn vm_error(e: VmError) -> (VtStatus, String) {
+    (VtStatus::from(&e), e.to_string())
+}
+
+/// Message of the last error raised on the calling thread, or null if the last call
+/// succeeded. The string is owned by the library and valid until the next call.
+#[no_mangle]
+pub extern "C" fn vt_last_error_message() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(ptr::null(), |message| message.as_ptr()))
+}
+
+/// Creates a program from the raw bytecode `code`, booting with `acc` and `lc`.
+///
+/// # Safety
+///
+/// `code` must point to `len` readable bytes, it may be null if `len` is 0.
+#[no_mangle]
+pub unsafe extern "C" fn vt_program_new(
+    code: *const u8,
+    len: usize,
+    acc: i32,
+    lc: i32,
+    program: *mut *mut VtProgram,
+) -> VtStatus {
+    guard(|| {
+        if program.is_null() {
+            return Err(null_pointer("program"));
+        }
+        let data = match (code.is_null(), len) {
+            (_, 0) => vec![],
+            (true, _) => return Err(null_pointer("code")),
+            (false, len) => slice::from_raw_parts(code, len).to_vec(),
+        };
+
+        *program = Box::into_raw(Box::new(VtProgram(Program::new(data, acc, lc))));
+        Ok(VtStatus::Ok)
+    })
+}
+
+/// Loads a program from the content of a program file, in the legacy or the
+/// container format.
+///
+/// # Safety
+///
+/// `bytes` must point to `len` readable bytes, it may be null if `len` is 0.
+#[no_mangle]
+pub unsafe extern "C" fn vt_program_load(
+    bytes: *const u8,
+    len: usize,
+    program: *mut *mut VtProgram,
+) -> VtStatus {
+    guard(|| {
+        if program.is_null() {
+            return Err(null_pointer("program"));
+        }
+        let bytes = match (bytes.is_null(), len) {
+            (_, 0) => &[][..],
+            (true, _) => return Err(null_pointer("bytes")),
+            (false, len) => slice::from_raw_parts(bytes, len),
+        };
+
+        let loaded = Program::from_bytes(bytes).map_err(load_error)?;
+        *program = Box::into_raw(Box::new(VtProgram(loaded)));
+        Ok(VtStatus::Ok)
+    })
+}
+
+/// Loads a program from the file at `path`.
+///
+/// # Safety
+///
+/// `path` must be a NUL terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn vt_program_load_file(
+    path: *const c_char,
+    program: *mut *mut VtProgram,
+) -> VtStatus {
+    guard(|| {
+        if path.is_null() {
+            return Err(null_pointer("path"));
+        }
+        if program.is_null() {
+            return Err(null_pointer("program"));
+        }
+        let path = CStr::from_ptr(path).to_str().map_err(|_| {
+            (
+                VtStatus::NonUtf8Path,
+                "path is not valid UTF-8".to_string(),
+            )
+        })?;
+
+        let loaded = Program::read_from_file(path).map_err(load_error)?;
+        *program = Box::into_raw(Box::new(VtProgram(loaded)));
+        Ok(VtStatus::Ok)
+    })
+}
+
+/// Releases a program. VMs created from it remain valid.
+///
+/// # Safety
+///
+/// `program` must be null or returned by this library, and not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn vt_program_free(program: *mut VtProgram) {
+    if !program.is_null() {
+        drop(Box::from_raw(program));
+    }
+}
+
+/// Creates a VM running a copy of `program` in `mode`, one of [`VtRunningMode`].
+///
+/// # Safety
+///
+/// `program` must be null or a live program returned by this library.
+#[no_mangle]
+pub unsafe extern "C" fn vt_vm_new(program: *const VtProgram, mode: u32, vm: *mut *mut VtVm) -> VtStatus {
+    guard(|| {
+        if program.is_null() {
+            return Err(null_pointer("program"));
+        }
+        if vm.is_null() {
+            return Err(null_pointer("vm"));
+        }
+        let mode = VtRunningMode::from_raw(mode).ok_or_else(|| {
+            (
+                VtStatus::InvalidArgument,
+                format!("unknown running mode {}", mode),
+            )
+        })?;
+
+        *vm = Box::into_raw(Box::new(VtVm(VM::new(mode, (*program).0.clone()))));
+        Ok(VtStatus::Ok)
+    })
+}
+
+fn outcome_status(result: Result<ExecutionOutcome, VmError>) -> Result<VtStatus, (VtStatus, String)> {
+    match result.map_err(vm_error)? {
+        ExecutionOutcome::Halted => Ok(VtStatus::Ok),
+        ExecutionOutcome::OutOfFuel { .. } => Ok(VtStatus::OutOfFuel),
+        ExecutionOutcome::Interrupted { .. } => Ok(VtStatus::Interrupted),
+    }
+}
+
+/// Runs the VM until it halts. Returns [`VtStatus::Ok`] once it halted, or the
+/// status of the error which stopped it.
+///
+/// # Safety
+///
+/// `vm` must be null or a live VM returned by this library.
+#[no_mangle]
+pub unsafe extern "C" fn vt_vm_run(vm: *mut VtVm) -> VtStatus {
+    guard(|| match vm.as_mut() {
+        Some(vm) => outcome_status(vm.0.run()),
+        None => Err(null_pointer("vm")),
+    })
+}
+
+/// Runs the VM for at most `fuel` instructions, returning [`VtStatus::OutOfFuel`]
+/// if it did not halt. Running it again resumes the execution.
+///
+/// # Safety
+///
+/// `vm` must be null or a live VM returned by this library.
+#[no_mangle]
+pub unsafe extern "C" fn vt_vm_run_with_fuel(vm: *mut VtVm, fuel: u64) -> VtStatus {
+    guard(|| match vm.as_mut() {
+        Some(vm) => outcome_status(vm.0.run_with_fuel(fuel)),
+        None => Err(null_pointer("vm")),
+    })
+}
+
+/// Restores the boot registers of the VM.
+///
+/// # Safety
+///
+/// `vm` must be null or a live VM returned by this library.
+#[no_mangle]
+pub unsafe extern "C" fn vt_vm_reset(vm: *mut VtVm) -> VtStatus {
+    guard(|| match vm.as_mut() {
+        Some(vm) => {
+            vm.0.reset();
+            Ok(VtStatus::Ok)
+        }
+        None => Err(null_pointer("vm")),
+    })
+}
+
+/// Instruction pointer of the VM, 0 if `vm` is null.
+///
+/// # Safety
+///
+/// `vm` must be null or a live VM returned by this library.
+#[no_mangle]
+pub unsafe extern "C" fn vt_vm_ip(vm: *const VtVm) -> u32 {
+    vm.as_ref().map_or(0, |vm| vm.0.ip())
+}
+
+/// Accumulator of the VM, 0 if `vm` is null.
+///
+/// # Safety
+///
+/// `vm` must be null or a live VM returned by this library.
+#[no_mangle]
+pub unsafe extern "C" fn vt_vm_acc(vm: *const VtVm) -> i32 {
+    vm.as_ref().map_or(0, |vm| vm.0.acc())
+}
+
+/// Loop counter of the VM, 0 if `vm` is null.
+///
+/// # Safety
+///
+/// `vm` must be null or a live VM returned by this library.
+#[no_mangle]
+pub unsafe extern "C" fn vt_vm_lc(vm: *const VtVm) -> i32 {
+    vm.as_ref().map_or(0, |vm| vm.0.lc())
+}
+
+/// True if the VM executed a HALT, false if `vm` is null.
+///
+/// # Safety
+///
+/// `vm` must be null or a live VM returned by this library.
+#[no_mangle]
+pub unsafe extern "C" fn vt_vm_is_halted(vm: *const VtVm) -> bool {
+    vm.as_ref().is_some_and(|vm| vm.0.is_halt())
+}
+
+/// Writes the report of the last run of the VM to `report`.
+///
+/// # Safety
+///
+/// `vm` must be null or a live VM returned by this library, `report` must be null
+/// or writable.
+#[no_mangle]
+pub unsafe extern "C" fn vt_vm_report(vm: *const VtVm, report: *mut VtReport) -> VtStatus {
+    guard(|| {
+        let vm = vm.as_ref().ok_or_else(|| null_pointer("vm"))?;
+        let report = report.as_mut().ok_or_else(|| null_pointer("report"))?;
+        *report = vm.0.report().into();
+        Ok(VtStatus::Ok)
+    })
+}
+
+/// Releases a VM.
+///
+/// # Safety
+///
+/// `vm` must be null or returned by this library, and not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn vt_vm_free(vm: *mut VtVm) {
+    if !vm.is_null() {
+        drop(Box::from_raw(vm));
+    }
+}
@@ -1 +1,6 @@
+pub mod capi;
+pub mod scenario;
 pub mod vm;
+
+#[cfg(feature = "python")]
+mod python;
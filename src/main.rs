@@ -1,6 +1,6 @@
 use std::path::PathBuf;
 
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 use vm::VM;
 
 pub mod vm;
@@ -10,17 +10,108 @@ pub mod vm;
 struct Args {
     #[clap(short, long)]
     path: PathBuf,
+    /// Initial value of register A, overriding the one of the program
+    #[clap(long, allow_negative_numbers = true)]
+    acc: Option<i32>,
+    /// Initial value of register L, overriding the one of the program
+    #[clap(long, allow_negative_numbers = true)]
+    lc: Option<i32>,
+    /// Running mode of the VM
+    #[clap(long, value_enum, default_value_t = Mode::Simple)]
+    mode: Mode,
+}
+
+/// Command line names of the [`vm::RunningMode`]s.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Mode {
+    Simple,
+    NoOptJitted,
+    OptJitted,
+    Decoded,
+    Threaded,
+    Closures,
+    Superinstructions,
+    Replicated,
+    TemplateJit,
+    NoOptCranelift,
+    OptCranelift,
+}
+
+impl From<Mode> for vm::RunningMode {
+    fn from(mode: Mode) -> Self {
+        match mode {
+            Mode::Simple => vm::RunningMode::Simple,
+            Mode::NoOptJitted => vm::RunningMode::NoOptJitted,
+            Mode::OptJitted => vm::RunningMode::OptJitted,
+            Mode::Decoded => vm::RunningMode::Decoded,
+            Mode::Threaded => vm::RunningMode::Threaded,
+            Mode::Closures => vm::RunningMode::Closures,
+            Mode::Superinstructions => vm::RunningMode::Superinstructions,
+            Mode::Replicated => vm::RunningMode::Replicated,
+            Mode::TemplateJit => vm::RunningMode::TemplateJit,
+            Mode::NoOptCranelift => vm::RunningMode::NoOptCranelift,
+            Mode::OptCranelift => vm::RunningMode::OptCranelift,
+        }
+    }
+}
+
+fn assemble_file(path: &std::path::Path) -> vm::program::Program {
+    let source = match std::fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(e) => {
+            eprintln!("[error] :: an error occurred when reading the source: {}", e);
+            std::process::exit(-1);
+        }
+    };
+
+    match vm::assembler::assemble(&source) {
+        Ok(assembly) => {
+            for warning in &assembly.warnings {
+                eprintln!("[warning] :: {}:{}", path.display(), warning);
+            }
+            let mut prog = assembly.program;
+            prog.filename = path.to_str().map(|s| s.to_string());
+            prog
+        }
+        Err(e) => {
+            eprintln!("[error] :: {}:{}", path.display(), e);
+            std::process::exit(-1);
+        }
+    }
 }
 
 fn main() {
     let args = Args::parse();
     // Load program from the command line
-    let prog = vm::program::Program::read_from_file(args.path);
+    let prog = if args.path.extension().is_some_and(|ext| ext == "vtasm") {
+        assemble_file(&args.path)
+    } else {
+        match vm::program::Program::read_from_file(&args.path) {
+            Ok(prog) => prog,
+            Err(e) => {
+                eprintln!(
+                    "[error] :: an error occurred when reading the program: {}",
+                    e
+                );
+                std::process::exit(-1);
+            }
+        }
+    };
     println!("{}", prog);
 
-    // Execute the program on a simple VM
-    let vm = VM::new(vm::RunningMode::Simple, prog);
+    // Execute the program in the mode and with the boot registers given by the user
+    let mut builder = VM::builder(prog).mode(args.mode.into());
+    if let Some(acc) = args.acc {
+        builder = builder.acc(acc);
+    }
+    if let Some(lc) = args.lc {
+        builder = builder.lc(lc);
+    }
+    let mut vm = builder.build();
     println!("[info] :: Before execution -> {}", vm);
-    vm.run();
+    if let Err(e) = vm.run() {
+        eprintln!("[error] :: the execution stopped with an error: {}", e);
+        std::process::exit(-1);
+    }
     println!("[info] :: After execution -> {}", vm);
 }
@@ -0,0 +1,281 @@
+//! Python extension module, built with `maturin develop --release`.
+//!
+//! ```python
+//! import vt_vm
+//!
+//! program = vt_vm.generate_scenario(10_000, 1, [1, 9, 1, 5, 5])
+//! vm = vt_vm.VM(program, vt_vm.RunningMode.OptJitted, acc=3)
+//! report = vm.run()
+//! print(report.acc, report.instructions, report.execute_ns)
+//! ```
+
+use std::path::PathBuf;
+
+use pyo3::{
+    create_exception,
+    exceptions::{PyException, PyValueError},
+    prelude::*,
+    types::{PyBytes, PyDict},
+};
+
+use crate::{
+    scenario,
+    vm::{assembler, error, program::Program, report::ExecutionReport, RunningMode, VM},
+};
+
+create_exception!(vt_vm, VmError, PyException, "Error raised while running a program.");
+create_exception!(vt_vm, ProgramError, PyException, "Error raised while loading a program.");
+
+#[pyclass(name = "RunningMode", module = "vt_vm", eq, eq_int)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PyRunningMode {
+    Simple,
+    NoOptJitted,
+    OptJitted,
+    Decoded,
+    Threaded,
+    Closures,
+    Superinstructions,
+    Replicated,
+    TemplateJit,
+    NoOptCranelift,
+    OptCranelift,
+}
+
+impl From<PyRunningMode> for RunningMode {
+    fn from(mode: PyRunningMode) -> Self {
+        match mode {
+            PyRunningMode::Simple => RunningMode::Simple,
+            PyRunningMode::NoOptJitted => RunningMode::NoOptJitted,
+            PyRunningMode::OptJitted => RunningMode::OptJitted,
+            PyRunningMode::Decoded => RunningMode::Decoded,
+            PyRunningMode::Threaded => RunningMode::Threaded,
+            PyRunningMode::Closures => RunningMode::Closures,
+            PyRunningMode::Superinstructions => RunningMode::Superinstructions,
+            PyRunningMode::Replicated => RunningMode::Replicated,
+            PyRunningMode::TemplateJit => RunningMode::TemplateJit,
+            PyRunningMode::NoOptCranelift => RunningMode::NoOptCranelift,
+            PyRunningMode::OptCranelift => RunningMode::OptCranelift,
+        }
+    }
+}
+
+#[pyclass(name = "Program", module = "vt_vm")]
+#[derive(Debug, Clone)]
+struct PyProgram(Program);
+
+#[pymethods]
+impl PyProgram {
+    #[new]
+    #[pyo3(signature = (code, acc = 0, lc = 0))]
+    fn new(code: Vec<u8>, acc: i32, lc: i32) -> Self {
+        Self(Program::new(code, acc, lc))
+    }
+
+    /// Loads a program from the content of a program file.
+    #[staticmethod]
+    fn from_bytes(bytes: &[u8]) -> PyResult<Self> {
+        Program::from_bytes(bytes)
+            .map(Self)
+            .map_err(|e| ProgramError::new_err(e.to_string()))
+    }
+
+    #[staticmethod]
+    fn from_file(path: PathBuf) -> PyResult<Self> {
+        Program::read_from_file(path)
+            .map(Self)
+            .map_err(|e| ProgramError::new_err(e.to_string()))
+    }
+
+    /// Assembles a `.vtasm` source.
+    #[staticmethod]
+    fn assemble(source: &str) -> PyResult<Self> {
+        assembler::assemble(source)
+            .map(|assembly| Self(assembly.program))
+            .map_err(|e| ProgramError::new_err(e.to_string()))
+    }
+
+    #[getter]
+    fn code<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new_bound(py, &self.0.data)
+    }
+
+    #[getter]
+    fn acc(&self) -> i32 {
+        self.0.initial_acc
+    }
+
+    #[getter]
+    fn lc(&self) -> i32 {
+        self.0.initial_lc
+    }
+
+    fn __len__(&self) -> usize {
+        self.0.data.len()
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "Program(len={}, acc={}, lc={})",
+            self.0.data.len(),
+            self.0.initial_acc,
+            self.0.initial_lc
+        )
+    }
+}
+
+/// Report of a run, see `ExecutionReport`. Durations are in nanoseconds.
+#[pyclass(name = "Report", module = "vt_vm", frozen, get_all)]
+#[derive(Debug, Clone)]
+struct PyReport {
+    ip: u32,
+    acc: i32,
+    lc: i32,
+    halted: bool,
+    instructions: u64,
+    back_edges_taken: u64,
+    back_edges_not_taken: u64,
+    dispatches_saved: u64,
+    compile_ns: u128,
+    verify_ns: u128,
+    execute_ns: u128,
+}
+
+impl From<ExecutionReport> for PyReport {
+    fn from(report: ExecutionReport) -> Self {
+        Self {
+            ip: report.ip,
+            acc: report.acc,
+            lc: report.lc,
+            halted: report.halted,
+            instructions: report.instructions,
+            back_edges_taken: report.back_edges_taken,
+            back_edges_not_taken: report.back_edges_not_taken,
+            dispatches_saved: report.dispatches_saved,
+            compile_ns: report.compile_time.as_nanos(),
+            verify_ns: report.verify_time.as_nanos(),
+            execute_ns: report.execute_time.as_nanos(),
+        }
+    }
+}
+
+#[pymethods]
+impl PyReport {
+    /// Fields of the report, to build a `pandas.DataFrame` from a list of reports.
+    fn to_dict<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let dict = PyDict::new_bound(py);
+        dict.set_item("ip", self.ip)?;
+        dict.set_item("acc", self.acc)?;
+        dict.set_item("lc", self.lc)?;
+        dict.set_item("halted", self.halted)?;
+        dict.set_item("instructions", self.instructions)?;
+        dict.set_item("back_edges_taken", self.back_edges_taken)?;
+        dict.set_item("back_edges_not_taken", self.back_edges_not_taken)?;
+        dict.set_item("dispatches_saved", self.dispatches_saved)?;
+        dict.set_item("compile_ns", self.compile_ns)?;
+        dict.set_item("verify_ns", self.verify_ns)?;
+        dict.set_item("execute_ns", self.execute_ns)?;
+        Ok(dict)
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "Report(ip={}, acc={}, lc={}, halted={}, instructions={})",
+            self.ip, self.acc, self.lc, self.halted, self.instructions
+        )
+    }
+}
+
+#[pyclass(name = "VM", module = "vt_vm")]
+struct PyVm(VM);
+
+#[pymethods]
+impl PyVm {
+    /// Boots a VM running `program`, `acc` and `lc` override its initial registers.
+    #[new]
+    #[pyo3(signature = (program, mode = PyRunningMode::Simple, *, acc = None, lc = None))]
+    fn new(program: &PyProgram, mode: PyRunningMode, acc: Option<i32>, lc: Option<i32>) -> Self {
+        let mut builder = VM::builder(program.0.clone()).mode(mode.into());
+        if let Some(acc) = acc {
+            builder = builder.acc(acc);
+        }
+        if let Some(lc) = lc {
+            builder = builder.lc(lc);
+        }
+        Self(builder.build())
+    }
+
+    /// Runs the program until it halts, or for at most `fuel` instructions.
+    #[pyo3(signature = (fuel = None))]
+    fn run(&mut self, py: Python<'_>, fuel: Option<u64>) -> PyResult<PyReport> {
+        // Other Python threads may run while the VM executes
+        let vm = &mut self.0;
+        py.allow_threads(|| match fuel {
+            Some(fuel) => vm.run_with_fuel(fuel),
+            None => vm.run(),
+        })
+        .map_err(|e: error::VmError| VmError::new_err(e.to_string()))?;
+
+        Ok(self.0.report().into())
+    }
+
+    /// Restores the boot registers.
+    fn reset(&mut self) {
+        self.0.reset();
+    }
+
+    /// Report of the last run.
+    fn report(&self) -> PyReport {
+        self.0.report().into()
+    }
+
+    #[getter]
+    fn ip(&self) -> u32 {
+        self.0.ip()
+    }
+
+    #[getter]
+    fn acc(&self) -> i32 {
+        self.0.acc()
+    }
+
+    #[getter]
+    fn lc(&self) -> i32 {
+        self.0.lc()
+    }
+
+    #[getter]
+    fn halted(&self) -> bool {
+        self.0.is_halt()
+    }
+
+    #[getter]
+    fn instructions(&self) -> u64 {
+        self.0.instructions()
+    }
+
+    fn __repr__(&self) -> String {
+        self.0.to_string()
+    }
+}
+
+/// Generates a program with the generator of `src/scenario/gen.c`, `probs` gives the
+/// relative probabilities of CLRA, INC3A, DECA, SETL and BACK7.
+#[pyfunction]
+fn generate_scenario(size: usize, seed: i32, probs: [i32; 5]) -> PyResult<PyProgram> {
+    scenario::generate(size, seed, probs)
+        .map(PyProgram)
+        .map_err(|e| PyValueError::new_err(e.to_string()))
+}
+
+#[pymodule]
+fn vt_vm(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyRunningMode>()?;
+    m.add_class::<PyProgram>()?;
+    m.add_class::<PyReport>()?;
+    m.add_class::<PyVm>()?;
+    m.add_function(wrap_pyfunction!(generate_scenario, m)?)?;
+    m.add("VmError", m.py().get_type_bound::<VmError>())?;
+    m.add("ProgramError", m.py().get_type_bound::<ProgramError>())?;
+    Ok(())
+}
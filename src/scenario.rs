@@ -0,0 +1,59 @@
+//! Pseudo-random programs produced by the generator of `src/scenario/gen.c`.
+//!
+//! The same size, seed and probabilities always give the same program, along
+//! with its initial registers.
+
+use std::{
+    os::raw::{c_char, c_int},
+    sync::Mutex,
+};
+
+use crate::vm::{error::ScenarioError, program::Program};
+
+// Add binding for `init` function contained inside `src/scenario/gen.c`.
+extern "C" {
+    fn init(
+        buf: *mut c_char,
+        size: c_int,
+        prob: *mut c_int,
+        seed: c_int,
+        r_a: *mut c_int,
+        r_l: *mut c_int,
+    );
+}
+
+// The generator keeps the state of its PRNG in a static variable, calls must not overlap
+static GENERATOR: Mutex<()> = Mutex::new(());
+
+/// Generates a program of `size` instructions ending with HALT. `probs` gives the
+/// relative probabilities of CLRA, INC3A, DECA, SETL and BACK7.
+pub fn generate(size: usize, seed: i32, mut probs: [i32; 5]) -> Result<Program, ScenarioError> {
+    if size == 0 {
+        return Err(ScenarioError::EmptyProgram);
+    }
+    // The generator sums the probabilities in an `int`, and replaces a SETL before a
+    // BACK7 by another opcode until it draws one of CLRA, INC3A or DECA
+    let sum = probs.iter().try_fold(0i32, |sum, prob| sum.checked_add(*prob));
+    if probs.iter().any(|prob| *prob < 0) || sum.is_none() || probs[..3].iter().all(|prob| *prob == 0) {
+        return Err(ScenarioError::InvalidProbabilities { probs });
+    }
+
+    let mut r_a: i32 = 0;
+    let mut r_l: i32 = 0;
+    let mut data = vec![0u8; size];
+
+    // `init` reseeds the generator, a poisoned lock leaves nothing inconsistent behind
+    let _guard = GENERATOR.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    unsafe {
+        init(
+            data.as_mut_ptr() as *mut c_char,
+            size as c_int,
+            probs.as_mut_ptr(),
+            seed,
+            &mut r_a,
+            &mut r_l,
+        )
+    }
+
+    Ok(Program::new(data, r_a, r_l))
+}
@@ -0,0 +1,272 @@
+//! Assembler turning `.vtasm` sources into [`Program`]s.
+//!
+//! ```text
+//! ; comments start with `;` or `#` and run until the end of the line
+//! .acc 3          ; initial value of register A
+//! .lc  0          ; initial value of register L
+//!         SETL
+//! body:   INC3A INC3A DECA
+//!         INC3A DECA DECA
+//!         BACK7 body  ; the label is optional, it lets the assembler check the body
+//!         HALT
+//! ```
+//!
+//! Mnemonics are the ones of [`OpCode`] and are case-insensitive. Several
+//! instructions can be written on the same line. `.byte <value>` emits a raw
+//! byte, as the [`disassembler`](super::disassembler) does for unknown opcodes.
+
+use std::collections::HashMap;
+
+use super::{
+    error::{AsmError, AsmErrorKind},
+    opcode::OpCode,
+    program::{Program, SourceLocation},
+};
+
+/// Result of a successful assembly.
+#[derive(Debug, Clone)]
+pub struct Assembly {
+    pub program: Program,
+    pub warnings: Vec<AsmWarning>,
+    /// Position in the source of every instruction, to store in a [`ProgramInfo`](super::program::ProgramInfo).
+    pub source_map: Vec<SourceLocation>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmWarning {
+    pub line: usize,
+    pub column: usize,
+    pub kind: AsmWarningKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AsmWarningKind {
+    /// The loop body between `label` and its BACK7 does not hold six instructions,
+    /// while BACK7 always jumps back to the sixth instruction before it.
+    LoopBodySize { label: String, size: isize },
+}
+
+impl std::fmt::Display for AsmWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}: ", self.line, self.column)?;
+        match &self.kind {
+            AsmWarningKind::LoopBodySize { label, size } => write!(
+                f,
+                "loop body of `{}` contains {} instructions, BACK7 always jumps back over 6",
+                label, size
+            ),
+        }
+    }
+}
+
+struct Token<'a> {
+    text: &'a str,
+    column: usize,
+}
+
+/// Splits a line into whitespace separated tokens, dropping the trailing comment.
+fn tokenize(line: &str) -> Vec<Token<'_>> {
+    let code = match line.find([';', '#']) {
+        Some(index) => &line[..index],
+        None => line,
+    };
+
+    let mut tokens = vec![];
+    let mut start = None;
+    for (index, c) in code.char_indices().chain(std::iter::once((code.len(), ' '))) {
+        match (start, c.is_whitespace()) {
+            (None, false) => start = Some(index),
+            (Some(s), true) => {
+                tokens.push(Token {
+                    text: &code[s..index],
+                    column: code[..s].chars().count() + 1,
+                });
+                start = None;
+            }
+            _ => (),
+        }
+    }
+    tokens
+}
+
+fn parse_integer(text: &str) -> Option<i32> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text.strip_prefix('+').unwrap_or(text)),
+    };
+
+    // `from_str_radix` and `parse` accept a sign of their own, a single one is allowed
+    let value = match digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        Some(hex) if hex.starts_with(|c: char| c.is_ascii_hexdigit()) => i64::from_str_radix(hex, 16).ok()?,
+        None if digits.starts_with(|c: char| c.is_ascii_digit()) => digits.parse::<i64>().ok()?,
+        _ => return None,
+    };
+
+    i32::try_from(if negative { -value } else { value }).ok()
+}
+
+fn is_label(text: &str) -> bool {
+    let mut chars = text.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// A BACK7 naming the label of its loop header, checked once all labels are known.
+struct LoopRef {
+    label: String,
+    offset: usize,
+    line: usize,
+    column: usize,
+}
+
+#[derive(Default)]
+struct Assembler {
+    data: Vec<u8>,
+    source_map: Vec<SourceLocation>,
+    acc: Option<i32>,
+    lc: Option<i32>,
+    labels: HashMap<String, usize>,
+    loops: Vec<LoopRef>,
+}
+
+impl Assembler {
+    fn emit(&mut self, instr: u8, line: usize, column: usize) {
+        self.source_map.push(SourceLocation {
+            offset: self.data.len() as u32,
+            line: line as u32,
+            column: column as u32,
+        });
+        self.data.push(instr);
+    }
+
+    fn line(&mut self, line: usize, tokens: Vec<Token<'_>>) -> Result<(), AsmError> {
+        let error = |token: &Token<'_>, kind| AsmError {
+            line,
+            column: token.column,
+            kind,
+        };
+
+        let mut tokens = tokens.into_iter().peekable();
+        while let Some(token) = tokens.next() {
+            if let Some(label) = token.text.strip_suffix(':') {
+                if !is_label(label) {
+                    return Err(error(&token, AsmErrorKind::InvalidLabel(label.to_string())));
+                }
+                if self.labels.insert(label.to_string(), self.data.len()).is_some() {
+                    return Err(error(&token, AsmErrorKind::DuplicateLabel(label.to_string())));
+                }
+            } else if let Some(directive) = token.text.strip_prefix('.') {
+                let operand = tokens.next().ok_or_else(|| {
+                    error(&token, AsmErrorKind::MissingOperand(token.text.to_string()))
+                })?;
+                let value = parse_integer(operand.text).ok_or_else(|| {
+                    error(&operand, AsmErrorKind::InvalidInteger(operand.text.to_string()))
+                })?;
+
+                let register = match directive {
+                    "acc" => &mut self.acc,
+                    "lc" => &mut self.lc,
+                    "byte" => {
+                        let byte = u8::try_from(value).map_err(|_| {
+                            error(&operand, AsmErrorKind::InvalidInteger(operand.text.to_string()))
+                        })?;
+                        self.emit(byte, line, token.column);
+                        continue;
+                    }
+                    _ => {
+                        return Err(error(
+                            &token,
+                            AsmErrorKind::UnknownDirective(token.text.to_string()),
+                        ))
+                    }
+                };
+                if register.replace(value).is_some() {
+                    return Err(error(
+                        &token,
+                        AsmErrorKind::DuplicateDirective(token.text.to_string()),
+                    ));
+                }
+            } else {
+                let opcode = token.text.parse::<OpCode>().map_err(|_| {
+                    error(&token, AsmErrorKind::UnknownMnemonic(token.text.to_string()))
+                })?;
+
+                match opcode {
+                    OpCode::SPILL => {
+                        return Err(error(
+                            &token,
+                            AsmErrorKind::ReservedOpcode(token.text.to_string()),
+                        ))
+                    }
+                    OpCode::BACK7 => {
+                        // An optional operand names the loop header
+                        let operand = tokens.next_if(|next| {
+                            !next.text.ends_with(':')
+                                && !next.text.starts_with('.')
+                                && next.text.parse::<OpCode>().is_err()
+                        });
+                        if let Some(operand) = operand {
+                            if !is_label(operand.text) {
+                                return Err(error(
+                                    &operand,
+                                    AsmErrorKind::UnexpectedOperand(operand.text.to_string()),
+                                ));
+                            }
+                            self.loops.push(LoopRef {
+                                label: operand.text.to_string(),
+                                offset: self.data.len(),
+                                line,
+                                column: operand.column,
+                            });
+                        }
+                    }
+                    _ => (),
+                }
+
+                self.emit(opcode.into(), line, token.column);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Assembly, AsmError> {
+        let mut warnings = vec![];
+        for loop_ref in &self.loops {
+            let header = self.labels.get(&loop_ref.label).ok_or_else(|| AsmError {
+                line: loop_ref.line,
+                column: loop_ref.column,
+                kind: AsmErrorKind::UndefinedLabel(loop_ref.label.clone()),
+            })?;
+
+            let size = loop_ref.offset as isize - *header as isize;
+            if size != 6 {
+                warnings.push(AsmWarning {
+                    line: loop_ref.line,
+                    column: loop_ref.column,
+                    kind: AsmWarningKind::LoopBodySize {
+                        label: loop_ref.label.clone(),
+                        size,
+                    },
+                });
+            }
+        }
+
+        let program = Program::new(self.data, self.acc.unwrap_or(0), self.lc.unwrap_or(0));
+
+        Ok(Assembly {
+            program,
+            warnings,
+            source_map: self.source_map,
+        })
+    }
+}
+
+/// Assembles a `.vtasm` source. Registers not set by `.acc` / `.lc` start at 0.
+pub fn assemble(source: &str) -> Result<Assembly, AsmError> {
+    let mut assembler = Assembler::default();
+    for (index, line) in source.lines().enumerate() {
+        assembler.line(index + 1, tokenize(line))?;
+    }
+    assembler.finish()
+}
@@ -0,0 +1,123 @@
+//! Parallel execution of independent runs.
+//!
+//! Each [`Job`] boots its own [`VM`], which is moved to one of the worker threads
+//! of a [`BatchRunner`]. Results are returned in the order of the jobs, whatever
+//! the order in which they complete.
+
+use std::{
+    num::NonZeroUsize,
+    sync::{mpsc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use super::{
+    builder::VmBuilder, error::VmError, program::Program, report::ExecutionReport,
+    ExecutionOutcome, RunningMode, VmState, VM,
+};
+
+/// A program to run, with the mode and the state the VM boots with.
+#[derive(Debug, Clone)]
+pub struct Job {
+    pub program: Program,
+    pub mode: RunningMode,
+    /// Boot state, the initial registers of the program if `None`.
+    pub state: Option<VmState>,
+}
+
+impl Job {
+    pub fn new(program: Program, mode: RunningMode) -> Self {
+        Self {
+            program,
+            mode,
+            state: None,
+        }
+    }
+
+    pub fn with_state(mut self, state: VmState) -> Self {
+        self.state = Some(state);
+        self
+    }
+
+    fn boot(self) -> VM {
+        let builder = VmBuilder::new(self.program).mode(self.mode);
+        match self.state {
+            Some(state) => builder.state(state).build(),
+            None => builder.build(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct JobResult {
+    pub outcome: Result<ExecutionOutcome, VmError>,
+    /// State of the VM at the end of the run.
+    pub state: VmState,
+    pub running_time: Duration,
+    pub report: ExecutionReport,
+}
+
+/// Runs [`Job`]s on a fixed number of worker threads.
+#[derive(Debug, Clone)]
+pub struct BatchRunner {
+    threads: usize,
+}
+
+impl Default for BatchRunner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BatchRunner {
+    /// Uses one worker per available CPU.
+    pub fn new() -> Self {
+        Self {
+            threads: thread::available_parallelism().map_or(1, NonZeroUsize::get),
+        }
+    }
+
+    /// Sets the number of workers, at least one.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Runs all `jobs`, returning their results in the same order.
+    pub fn run(&self, jobs: Vec<Job>) -> Vec<JobResult> {
+        let count = jobs.len();
+        let queue = Mutex::new(jobs.into_iter().map(Job::boot).enumerate());
+        let (sender, receiver) = mpsc::channel();
+
+        thread::scope(|scope| {
+            for _ in 0..self.threads.min(count) {
+                let sender = sender.clone();
+                let queue = &queue;
+                scope.spawn(move || loop {
+                    // Release the lock before running the VM
+                    let next = queue.lock().unwrap().next();
+                    let Some((index, mut vm)) = next else {
+                        break;
+                    };
+
+                    let outcome = vm.run();
+                    let result = JobResult {
+                        outcome,
+                        state: vm.state(),
+                        running_time: vm.running_time,
+                        report: vm.report(),
+                    };
+                    sender.send((index, result)).unwrap();
+                });
+            }
+        });
+        drop(sender);
+
+        let mut results: Vec<Option<JobResult>> = vec![None; count];
+        for (index, result) in receiver {
+            results[index] = Some(result);
+        }
+
+        results.into_iter().map(Option::unwrap).collect()
+    }
+}
@@ -0,0 +1,172 @@
+//! Configuration of a [`VM`] before it boots.
+//!
+//! ```ignore
+//! let vm = VmBuilder::new(program)
+//!     .mode(RunningMode::OptJitted)
+//!     .acc(42)
+//!     .lc(0)
+//!     .overflow_mode(OverflowMode::Trap)
+//!     .fuel(1_000_000)
+//!     .build();
+//! ```
+
+use std::{sync::Arc, time::Duration};
+
+use super::{
+    compiled::CompiledProgram,
+    error::VerifyError,
+    observer::{ExecutionObserver, NoopObserver},
+    program::Program,
+    report::ExecutionReport,
+    verifier, Interrupt, OverflowMode, RunningMode, VmState, VM,
+};
+
+/// Builds a [`VM`], overriding the boot registers stored in the [`Program`].
+#[derive(Debug, Clone)]
+pub struct VmBuilder {
+    program: Arc<Program>,
+    mode: RunningMode,
+    acc: Option<i32>,
+    lc: Option<i32>,
+    ip: u32,
+    halted: bool,
+    overflow_mode: OverflowMode,
+    fuel: Option<u64>,
+    timeout: Option<Duration>,
+    interrupt: Option<Interrupt>,
+    compiled: Option<CompiledProgram>,
+}
+
+impl VmBuilder {
+    /// Starts from the boot registers of `program`, in [`RunningMode::Simple`].
+    pub fn new(program: Program) -> Self {
+        Self::shared(Arc::new(program))
+    }
+
+    /// Starts from a program shared with compiled code, without copying it.
+    pub(crate) fn shared(program: Arc<Program>) -> Self {
+        Self {
+            program,
+            mode: RunningMode::Simple,
+            acc: None,
+            lc: None,
+            ip: 0,
+            halted: false,
+            overflow_mode: OverflowMode::default(),
+            fuel: None,
+            timeout: None,
+            interrupt: None,
+            compiled: None,
+        }
+    }
+
+    pub fn mode(mut self, mode: RunningMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Initial value of register A, instead of the one of the program.
+    pub fn acc(mut self, acc: i32) -> Self {
+        self.acc = Some(acc);
+        self
+    }
+
+    /// Initial value of register L, instead of the one of the program.
+    pub fn lc(mut self, lc: i32) -> Self {
+        self.lc = Some(lc);
+        self
+    }
+
+    /// Offset of the first instruction to execute, 0 by default.
+    pub fn ip(mut self, ip: u32) -> Self {
+        self.ip = ip;
+        self
+    }
+
+    /// Boots from the registers of `state`. A halted state gives a halted VM.
+    pub fn state(mut self, state: VmState) -> Self {
+        self.ip = state.ip;
+        self.acc = Some(state.acc);
+        self.lc = Some(state.lc);
+        self.halted = state.halted;
+        self
+    }
+
+    pub fn overflow_mode(mut self, overflow_mode: OverflowMode) -> Self {
+        self.overflow_mode = overflow_mode;
+        self
+    }
+
+    /// Maximum number of instructions retired by each call to [`VM::run`].
+    pub fn fuel(mut self, fuel: u64) -> Self {
+        self.fuel = Some(fuel);
+        self
+    }
+
+    /// Maximum duration of each call to [`VM::run`].
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Shares `interrupt` with the VM, instead of creating a handle of its own.
+    pub fn interrupt(mut self, interrupt: Interrupt) -> Self {
+        self.interrupt = Some(interrupt);
+        self
+    }
+
+    /// Reuses code compiled for the program of the builder.
+    pub(crate) fn compiled(mut self, compiled: CompiledProgram) -> Self {
+        self.compiled = Some(compiled);
+        self
+    }
+
+    pub fn build(self) -> VM {
+        self.build_with_observer(NoopObserver)
+    }
+
+    /// Builds a VM reporting its execution to `observer`.
+    pub fn build_with_observer<O: ExecutionObserver>(self, observer: O) -> VM<O> {
+        let boot = VmState {
+            ip: self.ip,
+            acc: self.acc.unwrap_or(self.program.initial_acc),
+            lc: self.lc.unwrap_or(self.program.initial_lc),
+            halted: self.halted,
+        };
+
+        VM {
+            state: boot,
+            boot,
+            fuel: None,
+            instructions: 0,
+            back_edges_taken: 0,
+            back_edges_not_taken: 0,
+            dispatches_saved: 0,
+            fuel_limit: self.fuel,
+            timeout: self.timeout,
+            interrupt: self.interrupt.unwrap_or_default(),
+            observer,
+            compiled: self.compiled,
+            report: ExecutionReport::default(),
+            running_time: Duration::new(0, 0),
+            running_program: self.program,
+            mode: self.mode,
+            overflow_mode: self.overflow_mode,
+        }
+    }
+
+    /// Same as [`VmBuilder::build`], but rejects programs for which the [`verifier`]
+    /// reports errors.
+    pub fn build_verified(self) -> Result<VM, VerifyError> {
+        let diagnostics: Vec<_> = verifier::verify(&self.program)
+            .into_iter()
+            .filter(|diagnostic| diagnostic.is_error())
+            .collect();
+
+        if !diagnostics.is_empty() {
+            return Err(VerifyError { diagnostics });
+        }
+
+        Ok(self.build())
+    }
+}
@@ -0,0 +1,227 @@
+//! Programs compiled ahead of their runs.
+//!
+//! A [`VM`] in a decoded or JIT [`RunningMode`] translates its program on its
+//! first run and keeps the code for the next ones. A [`CompiledProgram`] lifts the code out of
+//! the VM: it is compiled once and shared by every VM booted from it, whatever
+//! their initial registers.
+//!
+//! ```ignore
+//! let compiled = CompiledProgram::compile(&program, RunningMode::OptJitted)?;
+//! for acc in 0..10 {
+//!     let mut vm = compiled.builder().acc(acc).lc(0).build();
+//!     vm.run()?;
+//! }
+//! ```
+
+use std::{sync::Arc, time::Duration};
+
+#[cfg(feature = "cranelift")]
+use cranelift_codegen::settings::OptLevel;
+use inkwell::OptimizationLevel;
+
+#[cfg(feature = "cranelift")]
+use super::interpreter::cranelift::CraneliftCode;
+#[cfg(all(target_arch = "x86_64", unix))]
+use super::interpreter::template::TemplateCode;
+use super::{
+    builder::VmBuilder,
+    error::VmError,
+    interpreter::{
+        closures::ClosureProgram, decoded::DecodedProgram, jitted::JitCode, replicated::ReplicatedInterpreter,
+        simple::SimpleInterpreter, superinstructions::SuperProgram, threaded::ThreadedProgram, Interpreter,
+    },
+    observer::ExecutionObserver,
+    program::Program,
+    report::ExecutionReport,
+    ExecutionOutcome, OverflowMode, RunningMode, VM,
+};
+
+/// Code of a [`Program`] for a [`RunningMode`] and an [`OverflowMode`]. Cloning
+/// it shares the code, which is freed with the last clone.
+#[derive(Clone)]
+pub struct CompiledProgram {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    // Shared with every VM booted from the code
+    program: Arc<Program>,
+    mode: RunningMode,
+    overflow_mode: OverflowMode,
+    code: Code,
+}
+
+enum Code {
+    // The simple interpreter runs the bytes of the program
+    Interpreted,
+    // So does the replicated dispatch interpreter
+    Replicated,
+    Decoded(DecodedProgram),
+    Threaded(ThreadedProgram),
+    Closures(ClosureProgram),
+    Superinstructions(SuperProgram),
+    Jit(JitCode),
+    #[cfg(all(target_arch = "x86_64", unix))]
+    Template(TemplateCode),
+    #[cfg(feature = "cranelift")]
+    Cranelift(CraneliftCode),
+}
+
+impl std::fmt::Debug for CompiledProgram {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CompiledProgram")
+            .field("mode", &self.inner.mode)
+            .field("overflow_mode", &self.inner.overflow_mode)
+            .field("len", &self.inner.program.data.len())
+            .finish()
+    }
+}
+
+impl CompiledProgram {
+    /// Compiles `program` for `mode`, with the default overflow mode.
+    pub fn compile(program: &Program, mode: RunningMode) -> Result<Self, VmError> {
+        Self::compile_with_overflow_mode(program, mode, OverflowMode::default())
+    }
+
+    pub fn compile_with_overflow_mode(
+        program: &Program,
+        mode: RunningMode,
+        overflow_mode: OverflowMode,
+    ) -> Result<Self, VmError> {
+        let mut vm = VmBuilder::new(program.clone())
+            .mode(mode)
+            .overflow_mode(overflow_mode)
+            .build();
+        Self::compile_for(&mut vm)
+    }
+
+    /// Compiles the program of `vm` for its mode, errors report its registers.
+    pub(crate) fn compile_for<O: ExecutionObserver>(vm: &mut VM<O>) -> Result<Self, VmError> {
+        let code = match vm.mode {
+            RunningMode::Simple => Code::Interpreted,
+            RunningMode::Replicated => Code::Replicated,
+            RunningMode::NoOptJitted => Code::Jit(JitCode::compile(vm, OptimizationLevel::None)?),
+            RunningMode::OptJitted => Code::Jit(JitCode::compile(vm, OptimizationLevel::Default)?),
+            RunningMode::Decoded => Code::Decoded(DecodedProgram::decode(&vm.running_program)),
+            RunningMode::Threaded => Code::Threaded(ThreadedProgram::decode(&vm.running_program)),
+            RunningMode::Closures => Code::Closures(ClosureProgram::translate(&vm.running_program, vm.overflow_mode)),
+            RunningMode::Superinstructions => Code::Superinstructions(SuperProgram::rewrite(&vm.running_program)),
+            #[cfg(all(target_arch = "x86_64", unix))]
+            RunningMode::TemplateJit => Code::Template(TemplateCode::compile(vm)?),
+            #[cfg(not(all(target_arch = "x86_64", unix)))]
+            RunningMode::TemplateJit => {
+                return Err(VmError::JitCompilation {
+                    message: "the template JIT only emits x86-64 code for Unix systems".to_string(),
+                    ip: vm.state.ip,
+                    acc: vm.state.acc,
+                    lc: vm.state.lc,
+                })
+            }
+            #[cfg(feature = "cranelift")]
+            RunningMode::NoOptCranelift => Code::Cranelift(CraneliftCode::compile(vm, OptLevel::None)?),
+            #[cfg(feature = "cranelift")]
+            RunningMode::OptCranelift => Code::Cranelift(CraneliftCode::compile(vm, OptLevel::Speed)?),
+            #[cfg(not(feature = "cranelift"))]
+            RunningMode::NoOptCranelift | RunningMode::OptCranelift => {
+                return Err(VmError::JitCompilation {
+                    message: "the Cranelift backend needs the `cranelift` feature".to_string(),
+                    ip: vm.state.ip,
+                    acc: vm.state.acc,
+                    lc: vm.state.lc,
+                })
+            }
+        };
+
+        Ok(Self {
+            inner: Arc::new(Inner {
+                program: Arc::clone(&vm.running_program),
+                mode: vm.mode.clone(),
+                overflow_mode: vm.overflow_mode,
+                code,
+            }),
+        })
+    }
+
+    pub fn program(&self) -> &Program {
+        &self.inner.program
+    }
+
+    pub fn mode(&self) -> RunningMode {
+        self.inner.mode.clone()
+    }
+
+    pub fn overflow_mode(&self) -> OverflowMode {
+        self.inner.overflow_mode
+    }
+
+    /// Starts configuring a VM running this code. Changing the mode or the overflow
+    /// mode of the builder makes the VM compile the program again, as does running
+    /// LLVM code compiled without a fuel limit with one.
+    pub fn builder(&self) -> VmBuilder {
+        VmBuilder::shared(Arc::clone(&self.inner.program))
+            .mode(self.inner.mode.clone())
+            .overflow_mode(self.inner.overflow_mode)
+            .compiled(self.clone())
+    }
+
+    /// Time spent decoding or translating the program, or generating native code. Zero
+    /// in [`RunningMode::Simple`] and [`RunningMode::Replicated`], which run the opcodes
+    /// of the program.
+    pub fn compile_time(&self) -> Duration {
+        match &self.inner.code {
+            Code::Interpreted | Code::Replicated => Duration::ZERO,
+            Code::Decoded(code) => code.decode_time(),
+            Code::Threaded(code) => code.decode_time(),
+            Code::Closures(code) => code.translate_time(),
+            Code::Superinstructions(code) => code.rewrite_time(),
+            Code::Jit(code) => code.compile_time(),
+            #[cfg(all(target_arch = "x86_64", unix))]
+            Code::Template(code) => code.compile_time(),
+            #[cfg(feature = "cranelift")]
+            Code::Cranelift(code) => code.compile_time(),
+        }
+    }
+
+    /// Time spent verifying the LLVM module or the Cranelift function, zero for the other modes.
+    pub fn verify_time(&self) -> Duration {
+        match &self.inner.code {
+            Code::Jit(code) => code.verify_time(),
+            #[cfg(feature = "cranelift")]
+            Code::Cranelift(code) => code.verify_time(),
+            _ => Duration::ZERO,
+        }
+    }
+
+    /// Runs the program from the registers `acc` and `lc` until it halts.
+    pub fn run(&self, acc: i32, lc: i32) -> Result<ExecutionReport, VmError> {
+        let mut vm = self.builder().acc(acc).lc(lc).build();
+        vm.run()?;
+        Ok(vm.report())
+    }
+
+    /// True if `vm` can run this code.
+    pub(crate) fn matches<O: ExecutionObserver>(&self, vm: &VM<O>) -> bool {
+        // LLVM code compiled for unlimited runs does not check the fuel
+        let fuel = match &self.inner.code {
+            Code::Jit(code) => code.check_fuel() || vm.fuel.is_none(),
+            _ => true,
+        };
+        self.inner.mode == vm.mode && self.inner.overflow_mode == vm.overflow_mode && fuel
+    }
+
+    pub(crate) fn execute<O: ExecutionObserver>(&self, vm: &mut VM<O>) -> Result<ExecutionOutcome, VmError> {
+        match &self.inner.code {
+            Code::Interpreted => SimpleInterpreter.run(vm),
+            Code::Replicated => ReplicatedInterpreter.execute(vm),
+            Code::Decoded(code) => code.execute(vm),
+            Code::Threaded(code) => code.execute(vm),
+            Code::Closures(code) => code.execute(vm),
+            Code::Superinstructions(code) => code.execute(vm),
+            Code::Jit(code) => code.execute(vm),
+            #[cfg(all(target_arch = "x86_64", unix))]
+            Code::Template(code) => code.execute(vm),
+            #[cfg(feature = "cranelift")]
+            Code::Cranelift(code) => code.execute(vm),
+        }
+    }
+}
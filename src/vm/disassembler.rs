@@ -0,0 +1,149 @@
+//! Disassembler producing listings the [`assembler`](super::assembler) can read back.
+//!
+//! ```text
+//! .acc 3
+//! .lc 0
+//!         SETL                    ; 0000
+//! loop_0001:                      ; loop header, BACK7 at 0007
+//!         INC3A                   ; 0001
+//!         ...
+//!         BACK7 loop_0001         ; 0007 -> 0001
+//!         .byte 0xff              ; 0008
+//! ```
+//!
+//! Listings are produced one line at a time, so programs of any size can be
+//! written out without building the whole text in memory.
+
+use super::{opcode::OpCode, program::Program};
+
+/// Distance between a BACK7 and the first instruction of its loop body.
+const BACK7_DISTANCE: usize = 6;
+
+/// Content of the byte at a given offset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decoded {
+    Op(OpCode),
+    /// Bytes which are not user opcodes, SPILL included.
+    Byte(u8),
+}
+
+/// A single decoded instruction of the listing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ListingLine {
+    pub offset: usize,
+    pub decoded: Decoded,
+    /// The instruction is the target of the BACK7 at `offset + 6`.
+    pub loop_header: bool,
+    /// Target of a BACK7 instruction, `None` if the jump would underflow.
+    pub back7_target: Option<usize>,
+}
+
+/// Iterator over the [`ListingLine`]s of a program.
+#[derive(Debug, Clone)]
+pub struct Disassembler<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+pub fn decode(instr: u8) -> Decoded {
+    match OpCode::try_from(instr) {
+        Ok(OpCode::SPILL) | Err(_) => Decoded::Byte(instr),
+        Ok(opcode) => Decoded::Op(opcode),
+    }
+}
+
+/// Label of the loop header at `offset`, in the radix of the offsets of the listing.
+fn label(offset: usize) -> String {
+    format!("loop_{:04x}", offset)
+}
+
+impl<'a> Disassembler<'a> {
+    pub fn new(program: &'a Program) -> Self {
+        Self {
+            data: &program.data,
+            offset: 0,
+        }
+    }
+}
+
+impl<'a> Iterator for Disassembler<'a> {
+    type Item = ListingLine;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let offset = self.offset;
+        let decoded = decode(*self.data.get(offset)?);
+        self.offset += 1;
+
+        let loop_header = self.data.get(offset + BACK7_DISTANCE) == Some(&OpCode::BACK7.into());
+        let back7_target = match decoded {
+            Decoded::Op(OpCode::BACK7) => offset.checked_sub(BACK7_DISTANCE),
+            _ => None,
+        };
+
+        Some(ListingLine {
+            offset,
+            decoded,
+            loop_header,
+            back7_target,
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.data.len() - self.offset;
+        (remaining, Some(remaining))
+    }
+}
+
+impl std::fmt::Display for ListingLine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.loop_header {
+            let header = format!("{}:", label(self.offset));
+            writeln!(
+                f,
+                "{:<32}; loop header, BACK7 at {:04x}",
+                header,
+                self.offset + BACK7_DISTANCE
+            )?;
+        }
+
+        let (instr, comment) = match (self.decoded, self.back7_target) {
+            (Decoded::Op(OpCode::BACK7), Some(target)) => (
+                format!("BACK7 {}", label(target)),
+                format!("{:04x} -> {:04x}", self.offset, target),
+            ),
+            (Decoded::Op(OpCode::BACK7), None) => (
+                "BACK7".to_string(),
+                format!("{:04x} -> before the start of the program", self.offset),
+            ),
+            (Decoded::Op(opcode), _) => (opcode.to_string(), format!("{:04x}", self.offset)),
+            (Decoded::Byte(byte), _) => (
+                format!(".byte 0x{:02x}", byte),
+                format!("{:04x}", self.offset),
+            ),
+        };
+
+        write!(f, "        {:<24}; {}", instr, comment)
+    }
+}
+
+/// Full listing of a program, initial registers included.
+#[derive(Debug, Clone, Copy)]
+pub struct Listing<'a>(pub &'a Program);
+
+impl<'a> std::fmt::Display for Listing<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, ".acc {}", self.0.initial_acc)?;
+        writeln!(f, ".lc {}", self.0.initial_lc)?;
+
+        for line in Disassembler::new(self.0) {
+            writeln!(f, "{}", line)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Writes the listing of `program` to `out`, one line at a time.
+pub fn disassemble<W: std::io::Write>(program: &Program, out: &mut W) -> std::io::Result<()> {
+    write!(out, "{}", Listing(program))
+}
@@ -0,0 +1,295 @@
+/// Errors raised while executing a program on the [`VM`](super::VM).
+///
+/// Every variant carries the instruction pointer at which the fault happened and
+/// the value of the registers at that moment, so that a failing run can be
+/// inspected without re-executing it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VmError {
+    /// The byte at `ip` does not encode a valid (user-visible) opcode.
+    InvalidOpcode { opcode: u8, ip: u32, acc: i32, lc: i32 },
+    /// The instruction pointer left the program without reaching a HALT.
+    IpOutOfBounds { ip: u32, acc: i32, lc: i32 },
+    /// A taken BACK7 at `ip` would jump before the start of the program.
+    IpUnderflow { ip: u32, acc: i32, lc: i32 },
+    /// The instruction at `ip` overflowed a register with [`OverflowMode::Trap`](super::OverflowMode::Trap).
+    ArithmeticOverflow { ip: u32, acc: i32, lc: i32 },
+    /// LLVM or Cranelift rejected the code built for the program.
+    JitVerification { message: String, ip: u32, acc: i32, lc: i32 },
+    /// The JIT backend was unable to produce native code for the verified program.
+    JitCompilation { message: String, ip: u32, acc: i32, lc: i32 },
+}
+
+impl VmError {
+    /// Instruction pointer at which the error happened.
+    pub fn ip(&self) -> u32 {
+        match self {
+            VmError::InvalidOpcode { ip, .. }
+            | VmError::IpOutOfBounds { ip, .. }
+            | VmError::IpUnderflow { ip, .. }
+            | VmError::ArithmeticOverflow { ip, .. }
+            | VmError::JitVerification { ip, .. }
+            | VmError::JitCompilation { ip, .. } => *ip,
+        }
+    }
+
+    /// Value of the accumulator when the error happened.
+    pub fn acc(&self) -> i32 {
+        match self {
+            VmError::InvalidOpcode { acc, .. }
+            | VmError::IpOutOfBounds { acc, .. }
+            | VmError::IpUnderflow { acc, .. }
+            | VmError::ArithmeticOverflow { acc, .. }
+            | VmError::JitVerification { acc, .. }
+            | VmError::JitCompilation { acc, .. } => *acc,
+        }
+    }
+
+    /// Value of the loop counter when the error happened.
+    pub fn lc(&self) -> i32 {
+        match self {
+            VmError::InvalidOpcode { lc, .. }
+            | VmError::IpOutOfBounds { lc, .. }
+            | VmError::IpUnderflow { lc, .. }
+            | VmError::ArithmeticOverflow { lc, .. }
+            | VmError::JitVerification { lc, .. }
+            | VmError::JitCompilation { lc, .. } => *lc,
+        }
+    }
+}
+
+impl std::fmt::Display for VmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VmError::InvalidOpcode { opcode, .. } => write!(f, "invalid opcode 0x{:02x}", opcode)?,
+            VmError::IpOutOfBounds { .. } => write!(f, "instruction pointer out of bounds")?,
+            VmError::IpUnderflow { .. } => write!(f, "BACK7 jumps before the start of the program")?,
+            VmError::ArithmeticOverflow { .. } => write!(f, "arithmetic overflow")?,
+            VmError::JitVerification { message, .. } => {
+                write!(f, "error while verifying JIT code: {}", message)?
+            }
+            VmError::JitCompilation { message, .. } => {
+                write!(f, "unable to JIT compile VM code: {}", message)?
+            }
+        }
+        write!(f, " (ip: {}, acc: {}, lc: {})", self.ip(), self.acc(), self.lc())
+    }
+}
+
+impl std::error::Error for VmError {}
+
+/// Errors raised while loading a [`Program`](super::program::Program).
+#[derive(Debug)]
+pub enum ProgramLoadError {
+    /// The input is shorter than the header of its format.
+    TruncatedHeader { len: usize },
+    /// The underlying reader or file failed.
+    Io(std::io::Error),
+    /// The path of the program cannot be represented as UTF-8.
+    NonUtf8Path(std::path::PathBuf),
+    /// Opcode validation was requested and the byte at `offset` is not a valid opcode.
+    InvalidOpcode { opcode: u8, offset: usize },
+    /// The container was written by an unknown version of the format.
+    UnsupportedVersion { version: u16 },
+    /// The container sets flags this version of the loader does not understand.
+    UnsupportedFlags { flags: u16 },
+    /// The checksum stored in the container does not match its content.
+    ChecksumMismatch { expected: u32, found: u32 },
+    /// The code or the section starting at `offset` ends past the end of the input.
+    TruncatedSection { offset: usize },
+    /// The payload of the section starting at `offset` cannot be decoded.
+    InvalidSection { kind: u8, offset: usize },
+}
+
+impl std::fmt::Display for ProgramLoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProgramLoadError::TruncatedHeader { len } => {
+                write!(f, "truncated header: the input is only {} bytes long", len)
+            }
+            ProgramLoadError::Io(e) => write!(f, "I/O error: {}", e),
+            ProgramLoadError::NonUtf8Path(path) => {
+                write!(f, "path `{}` is not valid UTF-8", path.display())
+            }
+            ProgramLoadError::InvalidOpcode { opcode, offset } => {
+                write!(f, "invalid opcode 0x{:02x} at offset {}", opcode, offset)
+            }
+            ProgramLoadError::UnsupportedVersion { version } => {
+                write!(f, "unsupported container version {}", version)
+            }
+            ProgramLoadError::UnsupportedFlags { flags } => {
+                write!(f, "unsupported container flags 0x{:04x}", flags)
+            }
+            ProgramLoadError::ChecksumMismatch { expected, found } => write!(
+                f,
+                "checksum mismatch: expected 0x{:08x}, found 0x{:08x}",
+                expected, found
+            ),
+            ProgramLoadError::TruncatedSection { offset } => {
+                write!(f, "truncated section at offset {}", offset)
+            }
+            ProgramLoadError::InvalidSection { kind, offset } => {
+                write!(f, "invalid section of kind {} at offset {}", kind, offset)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ProgramLoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ProgramLoadError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for ProgramLoadError {
+    fn from(e: std::io::Error) -> Self {
+        ProgramLoadError::Io(e)
+    }
+}
+
+/// Error raised by the [`assembler`](super::assembler), pointing at the offending
+/// token in the source (lines and columns start from 1).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub line: usize,
+    pub column: usize,
+    pub kind: AsmErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AsmErrorKind {
+    UnknownMnemonic(String),
+    UnknownDirective(String),
+    /// The directive requires an operand which is missing.
+    MissingOperand(String),
+    InvalidInteger(String),
+    /// The operand is not allowed after this instruction.
+    UnexpectedOperand(String),
+    /// SPILL is only used internally by the JIT.
+    ReservedOpcode(String),
+    InvalidLabel(String),
+    DuplicateLabel(String),
+    UndefinedLabel(String),
+    DuplicateDirective(String),
+}
+
+impl std::fmt::Display for AsmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}: ", self.line, self.column)?;
+        match &self.kind {
+            AsmErrorKind::UnknownMnemonic(m) => write!(f, "unknown mnemonic `{}`", m),
+            AsmErrorKind::UnknownDirective(d) => write!(f, "unknown directive `{}`", d),
+            AsmErrorKind::MissingOperand(d) => write!(f, "missing operand for `{}`", d),
+            AsmErrorKind::InvalidInteger(i) => write!(f, "invalid integer `{}`", i),
+            AsmErrorKind::UnexpectedOperand(o) => write!(f, "unexpected operand `{}`", o),
+            AsmErrorKind::ReservedOpcode(m) => write!(f, "`{}` is reserved for internal use", m),
+            AsmErrorKind::InvalidLabel(l) => write!(f, "invalid label `{}`", l),
+            AsmErrorKind::DuplicateLabel(l) => write!(f, "label `{}` is already defined", l),
+            AsmErrorKind::UndefinedLabel(l) => write!(f, "label `{}` is not defined", l),
+            AsmErrorKind::DuplicateDirective(d) => write!(f, "directive `{}` is repeated", d),
+        }
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+/// The [`verifier`](super::verifier) found errors in a program.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifyError {
+    pub diagnostics: Vec<super::verifier::Diagnostic>,
+}
+
+impl std::fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "the program failed verification")?;
+        for diagnostic in &self.diagnostics {
+            write!(f, "\n  {}", diagnostic)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for VerifyError {}
+
+/// Errors raised while decoding or restoring a [`Snapshot`](super::snapshot::Snapshot).
+#[derive(Debug)]
+pub enum SnapshotError {
+    /// The input does not start with the snapshot magic number.
+    InvalidMagic,
+    /// The snapshot was written by an unknown version of the format.
+    UnsupportedVersion { version: u16 },
+    /// The input is shorter than a snapshot.
+    Truncated { len: usize },
+    /// The JSON document is not a valid snapshot.
+    Json(serde_json::Error),
+    /// The snapshot was taken while running another program.
+    ProgramMismatch { expected: u64, found: u64 },
+    /// The instruction pointer lies past the end of the program.
+    IpOutOfBounds { ip: u32, len: usize },
+}
+
+impl std::fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SnapshotError::InvalidMagic => write!(f, "not a VM snapshot"),
+            SnapshotError::UnsupportedVersion { version } => {
+                write!(f, "unsupported snapshot version {}", version)
+            }
+            SnapshotError::Truncated { len } => {
+                write!(f, "truncated snapshot: the input is only {} bytes long", len)
+            }
+            SnapshotError::Json(e) => write!(f, "invalid JSON snapshot: {}", e),
+            SnapshotError::ProgramMismatch { expected, found } => write!(
+                f,
+                "the snapshot belongs to program {:016x}, not to {:016x}",
+                found, expected
+            ),
+            SnapshotError::IpOutOfBounds { ip, len } => write!(
+                f,
+                "instruction pointer {} is out of a program of {} bytes",
+                ip, len
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SnapshotError::Json(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<serde_json::Error> for SnapshotError {
+    fn from(e: serde_json::Error) -> Self {
+        SnapshotError::Json(e)
+    }
+}
+
+/// Parameters rejected by the scenario generator of `src/scenario/gen.c`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScenarioError {
+    /// The generator needs room for at least the final HALT.
+    EmptyProgram,
+    /// Probabilities must be non-negative and their sum must fit in an `i32`. One of
+    /// CLRA, INC3A and DECA must have a non-zero probability, otherwise the generator
+    /// never terminates.
+    InvalidProbabilities { probs: [i32; 5] },
+}
+
+impl std::fmt::Display for ScenarioError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScenarioError::EmptyProgram => write!(f, "a scenario holds at least one instruction"),
+            ScenarioError::InvalidProbabilities { probs } => {
+                write!(f, "invalid opcode probabilities {:?}", probs)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ScenarioError {}
@@ -1,15 +1,76 @@
-use super::VM;
+use crate::measure_time;
 
+use self::simple::SimpleInterpreter;
+use super::{error::VmError, observer::ExecutionObserver, ExecutionOutcome, VM};
+
+pub mod closures;
+#[cfg(feature = "cranelift")]
+pub mod cranelift;
+pub mod decoded;
 pub mod jitted;
+pub mod replicated;
 pub mod simple;
+pub mod superinstructions;
+#[cfg(all(target_arch = "x86_64", unix))]
+pub mod template;
+pub mod threaded;
 
 pub trait Interpreter {
-    fn run(&self, vm: &VM);
-    fn halt(&self, vm: &VM, instr: u8);
-    fn clra(&self, vm: &VM, instr: u8);
-    fn inc3a(&self, vm: &VM, instr: u8);
-    fn deca(&self, vm: &VM, instr: u8);
-    fn setl(&self, vm: &VM, instr: u8);
-    fn back7(&self, vm: &VM, instr: u8);
-    fn spill(&self, vm: &VM, instr: u8);
+    fn run<O: ExecutionObserver>(&self, vm: &mut VM<O>) -> Result<ExecutionOutcome, VmError>;
+    fn halt<O: ExecutionObserver>(&self, vm: &mut VM<O>, instr: u8) -> Result<(), VmError>;
+    fn clra<O: ExecutionObserver>(&self, vm: &mut VM<O>, instr: u8) -> Result<(), VmError>;
+    fn inc3a<O: ExecutionObserver>(&self, vm: &mut VM<O>, instr: u8) -> Result<(), VmError>;
+    fn deca<O: ExecutionObserver>(&self, vm: &mut VM<O>, instr: u8) -> Result<(), VmError>;
+    fn setl<O: ExecutionObserver>(&self, vm: &mut VM<O>, instr: u8) -> Result<(), VmError>;
+    fn back7<O: ExecutionObserver>(&self, vm: &mut VM<O>, instr: u8) -> Result<(), VmError>;
+    fn spill<O: ExecutionObserver>(&self, vm: &mut VM<O>, instr: u8) -> Result<(), VmError>;
+}
+
+/// Runs `vm` until it halts or stops, with `dispatch` executing as much of the program as
+/// it can at a time. It returns true if the instruction at IP is left to the simple
+/// interpreter: an instruction which faults or may fault, or an IP no code starts at.
+pub(super) fn drive<O, F>(vm: &mut VM<O>, mut dispatch: F) -> Result<ExecutionOutcome, VmError>
+where
+    O: ExecutionObserver,
+    F: FnMut(&mut VM<O>) -> Result<bool, VmError>,
+{
+    let mut result = Ok(ExecutionOutcome::Halted);
+    let elapsed_time = measure_time!({
+        loop {
+            if vm.is_halt() {
+                break;
+            }
+
+            if let Some(interrupted) = vm.take_interrupt() {
+                result = Ok(interrupted);
+                break;
+            }
+
+            if vm.fuel == Some(0) {
+                result = Ok(vm.out_of_fuel());
+                break;
+            }
+
+            // A state restored by the user may point anywhere, the simple interpreter
+            // reports an IP past the end of the program
+            let step = if (vm.state.ip as usize) < vm.running_program.data.len() {
+                dispatch(vm)
+            } else {
+                Ok(true)
+            };
+            // Once the fuel is exhausted, the top of the loop reports it
+            let stepped = match step {
+                Ok(true) if vm.fuel != Some(0) => SimpleInterpreter.step(vm),
+                Ok(_) => Ok(()),
+                Err(e) => Err(e),
+            };
+            if let Err(e) = stepped {
+                result = Err(e);
+                break;
+            }
+        }
+    });
+    vm.running_time = elapsed_time;
+
+    result
 }
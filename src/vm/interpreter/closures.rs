@@ -0,0 +1,295 @@
+//! Backend translating the program into boxed closures.
+//!
+//! The program is split into blocks starting at its entry, at every BACK7 target
+//! and after every BACK7 or HALT. Each block becomes a closure: runs of CLRA,
+//! INC3A and DECA fold into a single closure, and a block ending with a BACK7
+//! jumping back to its own start becomes a closure looping over its body.
+//!
+//! The dispatch loop calls the closure of the block starting at IP. Where no block
+//! starts, e.g. at a faulting instruction or when the VM was stopped in the middle
+//! of a block, and when the fuel left cannot cover a whole block, the simple
+//! interpreter executes a single instruction instead.
+
+use std::time::Duration;
+
+use crate::{
+    measure_time,
+    vm::{error::VmError, observer::ExecutionObserver, program::Program, ExecutionOutcome, OverflowMode, VM},
+};
+
+use super::{decoded::Instr, threaded::Registers};
+
+/// Straight-line part of a block.
+type Segment = Box<dyn Fn(&mut Registers) -> Result<(), VmError> + Send + Sync>;
+
+/// Closure of a block, returning false if the dispatch loop has to stop.
+type BlockFn = Box<dyn Fn(&mut Registers) -> Result<bool, VmError> + Send + Sync>;
+
+struct Block {
+    // Instructions retired by a pass over the block
+    cost: u64,
+    run: BlockFn,
+}
+
+/// Instruction ending a block.
+enum Terminator {
+    /// The next instruction starts another block
+    Next,
+    Halt,
+    Back7 { target: u32 },
+    /// The instruction faults, or may fault, the simple interpreter executes it
+    Fault,
+}
+
+/// Folds a run of CLRA, INC3A and DECA, `None` standing for CLRA, into a segment.
+fn fold(steps: Vec<Option<i32>>, overflow_mode: OverflowMode) -> Segment {
+    let len = steps.len() as u32;
+
+    // Offsets of ACC from its value before the run, up to the first CLRA
+    let (mut offset, mut low, mut high) = (0i64, 0i64, 0i64);
+    // Value of ACC after the first CLRA, which does not depend on the run
+    let mut cleared: Option<i64> = None;
+    let mut cleared_fits = true;
+    for step in &steps {
+        match (step, cleared) {
+            (None, _) => cleared = Some(0),
+            (Some(delta), None) => {
+                offset += *delta as i64;
+                low = low.min(offset);
+                high = high.max(offset);
+            }
+            (Some(delta), Some(value)) => {
+                let value = value + *delta as i64;
+                cleared_fits &= i32::try_from(value).is_ok();
+                cleared = Some(value);
+            }
+        }
+    }
+
+    match (overflow_mode, cleared) {
+        // Wrapping additions are associative, the run is a single one
+        (OverflowMode::Wrapping, None) => {
+            let delta = offset as i32;
+            Box::new(move |regs| {
+                regs.acc = regs.acc.wrapping_add(delta);
+                regs.ip += len;
+                regs.fuel -= len as u64;
+                Ok(())
+            })
+        }
+        (OverflowMode::Wrapping, Some(value)) => {
+            let value = value as i32;
+            Box::new(move |regs| {
+                regs.acc = value;
+                regs.ip += len;
+                regs.fuel -= len as u64;
+                Ok(())
+            })
+        }
+        _ => Box::new(move |regs| {
+            let acc = regs.acc as i64;
+            if cleared_fits && i32::try_from(acc + low).is_ok() && i32::try_from(acc + high).is_ok() {
+                regs.acc = cleared.unwrap_or(acc + offset) as i32;
+                regs.ip += len;
+                regs.fuel -= len as u64;
+                return Ok(());
+            }
+
+            // The run saturates or traps, execute it one instruction at a time
+            for step in &steps {
+                regs.acc = match step {
+                    None => 0,
+                    Some(delta) => regs.add(regs.acc, *delta)?,
+                };
+                regs.retire();
+            }
+            Ok(())
+        }),
+    }
+}
+
+fn setl() -> Segment {
+    Box::new(|regs| {
+        regs.lc = regs.acc;
+        regs.retire();
+        Ok(())
+    })
+}
+
+/// Builds the closure of a block starting at `start`, made of `body` and `terminator`.
+fn block(start: u32, cost: u64, body: Vec<Segment>, terminator: Terminator) -> BlockFn {
+    match terminator {
+        Terminator::Next | Terminator::Fault => Box::new(move |regs| {
+            for segment in &body {
+                segment(regs)?;
+            }
+            Ok(true)
+        }),
+        Terminator::Halt => Box::new(move |regs| {
+            for segment in &body {
+                segment(regs)?;
+            }
+            regs.halted = true;
+            regs.fuel -= 1;
+            Ok(false)
+        }),
+        // A loop over the block itself runs every iteration without going back
+        // to the dispatch loop
+        Terminator::Back7 { target } if target == start => Box::new(move |regs| loop {
+            for segment in &body {
+                segment(regs)?;
+            }
+            regs.lc = regs.add(regs.lc, -1)?;
+            if regs.lc <= 0 {
+                regs.not_taken += 1;
+                regs.retire();
+                return Ok(true);
+            }
+            regs.ip = target;
+            regs.taken += 1;
+            regs.fuel -= 1;
+            if regs.interrupt.is_interrupted() {
+                return Ok(false);
+            }
+            // The simple interpreter steps the iteration the fuel cannot cover
+            if regs.fuel < cost {
+                return Ok(true);
+            }
+        }),
+        Terminator::Back7 { target } => Box::new(move |regs| {
+            for segment in &body {
+                segment(regs)?;
+            }
+            regs.lc = regs.add(regs.lc, -1)?;
+            if regs.lc <= 0 {
+                regs.not_taken += 1;
+                regs.retire();
+                return Ok(true);
+            }
+            regs.ip = target;
+            regs.taken += 1;
+            regs.fuel -= 1;
+            Ok(!regs.interrupt.is_interrupted())
+        }),
+    }
+}
+
+/// Translates every block of `program`, indexed by the IP at which it starts.
+fn translate_blocks(program: &Program, overflow_mode: OverflowMode) -> Vec<Option<Block>> {
+    let mut code: Vec<Instr> = program
+        .data
+        .iter()
+        .enumerate()
+        .map(|(index, instr)| Instr::decode(index as u32, *instr))
+        .collect();
+    code.push(Instr::End);
+
+    let mut leaders = vec![false; code.len() + 1];
+    leaders[0] = true;
+    for (index, instr) in code.iter().enumerate() {
+        match instr {
+            Instr::Back7 { target } => {
+                leaders[*target as usize] = true;
+                leaders[index + 1] = true;
+            }
+            Instr::Back7Underflow | Instr::Halt => leaders[index + 1] = true,
+            _ => (),
+        }
+    }
+
+    let mut blocks = Vec::with_capacity(code.len());
+    for start in 0..code.len() {
+        if !leaders[start] {
+            blocks.push(None);
+            continue;
+        }
+
+        let mut body = vec![];
+        let mut run = vec![];
+        let mut ip = start;
+        let terminator = loop {
+            match code[ip] {
+                Instr::Clra => run.push(None),
+                Instr::Inc3a => run.push(Some(3)),
+                Instr::Deca => run.push(Some(-1)),
+                Instr::Setl => {
+                    if !run.is_empty() {
+                        body.push(fold(std::mem::take(&mut run), overflow_mode));
+                    }
+                    body.push(setl());
+                }
+                Instr::Halt => break Terminator::Halt,
+                Instr::Back7 { target } => break Terminator::Back7 { target },
+                Instr::Back7Underflow | Instr::Invalid(_) | Instr::End => break Terminator::Fault,
+            }
+            ip += 1;
+            if leaders[ip] {
+                break Terminator::Next;
+            }
+        };
+        if !run.is_empty() {
+            body.push(fold(run, overflow_mode));
+        }
+
+        let cost = match terminator {
+            Terminator::Halt | Terminator::Back7 { .. } => ip - start + 1,
+            Terminator::Next | Terminator::Fault => ip - start,
+        } as u64;
+        // Nothing to run before a fault, the simple interpreter reports it
+        blocks.push((cost > 0).then(|| Block {
+            cost,
+            run: block(start as u32, cost, body, terminator),
+        }));
+    }
+
+    blocks
+}
+
+pub struct ClosureProgram {
+    // Block starting at each IP, if any
+    blocks: Box<[Option<Block>]>,
+    translate_time: Duration,
+}
+
+impl ClosureProgram {
+    pub fn translate(program: &Program, overflow_mode: OverflowMode) -> Self {
+        let blocks;
+        let translate_time = measure_time!({
+            blocks = translate_blocks(program, overflow_mode);
+        });
+
+        Self {
+            blocks: blocks.into_boxed_slice(),
+            translate_time,
+        }
+    }
+
+    pub fn translate_time(&self) -> Duration {
+        self.translate_time
+    }
+
+    pub fn execute<O: ExecutionObserver>(&self, vm: &mut VM<O>) -> Result<ExecutionOutcome, VmError> {
+        super::drive(vm, |vm| self.dispatch(vm))
+    }
+
+    /// Runs blocks until HALT, a fault, a taken BACK7 seeing a pending interrupt, or
+    /// an IP at which no block can run. Returns true in the last case.
+    fn dispatch<O: ExecutionObserver>(&self, vm: &mut VM<O>) -> Result<bool, VmError> {
+        let mut regs = Registers::load(vm);
+
+        let result = loop {
+            match self.blocks.get(regs.ip as usize) {
+                Some(Some(block)) if regs.fuel >= block.cost => match (block.run)(&mut regs) {
+                    Ok(true) => (),
+                    Ok(false) => break Ok(false),
+                    Err(e) => break Err(e),
+                },
+                _ => break Ok(true),
+            }
+        };
+
+        regs.store(vm);
+
+        result
+    }
+}
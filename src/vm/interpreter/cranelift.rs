@@ -0,0 +1,415 @@
+//! JIT backend generating native code with Cranelift instead of LLVM.
+//!
+//! The function is built from the basic blocks of [`Program::build_basic_blocks`],
+//! with the control flow of the LLVM backend: an entry switch on IP, a fuel check
+//! at the start of every block, an exit per trap and an interrupt check after every
+//! taken BACK7. It follows the convention of [`RunFunc`] and is run by the same
+//! loop, so both backends report their results identically. Cranelift generates
+//! code much faster than LLVM, which optimizes it further.
+//!
+//! [`Program::build_basic_blocks`]: crate::vm::program::Program::build_basic_blocks
+
+use std::time::{Duration, Instant};
+
+use cranelift_codegen::{
+    ir::{condcodes::IntCC, types, AbiParam, Block, InstBuilder, MemFlags, Value},
+    settings::{self, Configurable, OptLevel},
+    verify_function,
+};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Switch, Variable};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, Linkage, Module};
+
+use crate::vm::{error::VmError, observer::ExecutionObserver, opcode::OpCode, ExecutionOutcome, OverflowMode, VM};
+
+use super::jitted::{
+    self, RunFunc, STATUS_HALT, STATUS_INTERRUPTED, STATUS_NO_ENTRY, STATUS_OUT_OF_FUEL, STATUS_OVERFLOW,
+};
+
+const FUNC_NAME: &str = "vt_vm";
+
+/// Arguments of the function and the variables holding the registers while it runs.
+struct Registers {
+    acc_ptr: Value,
+    lc_ptr: Value,
+    ip_ptr: Value,
+    fuel_ptr: Value,
+    taken_ptr: Value,
+    not_taken_ptr: Value,
+    interrupt_ptr: Value,
+    acc: Variable,
+    lc: Variable,
+    fuel: Variable,
+    taken: Variable,
+    not_taken: Variable,
+}
+
+/// Builds the function running a program, one instruction at a time.
+struct Translator<'a, 'f> {
+    builder: FunctionBuilder<'f>,
+    regs: &'a Registers,
+    overflow_mode: OverflowMode,
+    // First block of the program starting at each IP, if any
+    blocks: &'a [Option<Block>],
+}
+
+impl<'a, 'f> Translator<'a, 'f> {
+    /// Writes the registers back through the function parameters and returns `status`.
+    fn build_exit(&mut self, ip: u32, status: u32) {
+        let flags = MemFlags::trusted();
+        let acc = self.builder.use_var(self.regs.acc);
+        self.builder.ins().store(flags, acc, self.regs.acc_ptr, 0);
+        let lc = self.builder.use_var(self.regs.lc);
+        self.builder.ins().store(flags, lc, self.regs.lc_ptr, 0);
+        let ip = self.builder.ins().iconst(types::I32, ip as i64);
+        self.builder.ins().store(flags, ip, self.regs.ip_ptr, 0);
+        let fuel = self.builder.use_var(self.regs.fuel);
+        self.builder.ins().store(flags, fuel, self.regs.fuel_ptr, 0);
+        let taken = self.builder.use_var(self.regs.taken);
+        self.builder.ins().store(flags, taken, self.regs.taken_ptr, 0);
+        let not_taken = self.builder.use_var(self.regs.not_taken);
+        self.builder.ins().store(flags, not_taken, self.regs.not_taken_ptr, 0);
+
+        let status = self.builder.ins().iconst(types::I32, status as i64);
+        self.builder.ins().return_(&[status]);
+    }
+
+    /// Checks that the remaining fuel covers the `cost` instructions of the block
+    /// starting at `ip`, and consumes it.
+    fn begin_block(&mut self, ip: u32, cost: u64) {
+        if cost == 0 {
+            return;
+        }
+
+        let exit = self.builder.create_block();
+        let cont = self.builder.create_block();
+        let fuel = self.builder.use_var(self.regs.fuel);
+        let exhausted = self.builder.ins().icmp_imm(IntCC::UnsignedLessThan, fuel, cost as i64);
+        self.builder.ins().brif(exhausted, exit, &[], cont, &[]);
+
+        self.builder.switch_to_block(exit);
+        self.build_exit(ip, STATUS_OUT_OF_FUEL);
+
+        self.builder.switch_to_block(cont);
+        let fuel = self.builder.ins().iadd_imm(fuel, -(cost as i64));
+        self.builder.def_var(self.regs.fuel, fuel);
+    }
+
+    /// Builds `value + delta` following the overflow mode of the VM, so that the
+    /// compiled code behaves exactly as the simple interpreter. A trap exits at `ip`,
+    /// giving back the fuel of the `unretired` instructions of the block.
+    fn build_add(&mut self, value: Value, delta: i32, ip: u32, unretired: u64) -> Value {
+        let wrapped = self.builder.ins().iadd_imm(value, delta as i64);
+        if self.overflow_mode == OverflowMode::Wrapping {
+            return wrapped;
+        }
+
+        // The operand overflows if it is past the bound minus the delta
+        let (cond, bound, limit) = if delta >= 0 {
+            (IntCC::SignedGreaterThan, i32::MAX - delta, i32::MAX)
+        } else {
+            (IntCC::SignedLessThan, i32::MIN - delta, i32::MIN)
+        };
+        let overflow = self.builder.ins().icmp_imm(cond, value, bound as i64);
+
+        match self.overflow_mode {
+            OverflowMode::Saturating => {
+                let limit = self.builder.ins().iconst(types::I32, limit as i64);
+                self.builder.ins().select(overflow, limit, wrapped)
+            }
+            _ => {
+                let trap = self.builder.create_block();
+                let cont = self.builder.create_block();
+                self.builder.ins().brif(overflow, trap, &[], cont, &[]);
+
+                self.builder.switch_to_block(trap);
+                let fuel = self.builder.use_var(self.regs.fuel);
+                let fuel = self.builder.ins().iadd_imm(fuel, unretired as i64);
+                self.builder.def_var(self.regs.fuel, fuel);
+                self.build_exit(ip, STATUS_OVERFLOW);
+
+                self.builder.switch_to_block(cont);
+                wrapped
+            }
+        }
+    }
+
+    /// Increments the 64 bits counter held by `counter`.
+    fn build_count(&mut self, counter: Variable) {
+        let value = self.builder.use_var(counter);
+        let incremented = self.builder.ins().iadd_imm(value, 1);
+        self.builder.def_var(counter, incremented);
+    }
+
+    /// Builds the BACK7 at `ip`, checking the interrupt flag when jumping back.
+    fn build_back7(&mut self, ip: u32, unretired: u64) {
+        let lc = self.builder.use_var(self.regs.lc);
+        let lc = self.build_add(lc, -1, ip, unretired);
+        self.builder.def_var(self.regs.lc, lc);
+
+        let check = self.builder.create_block();
+        let interrupted = self.builder.create_block();
+        let cont = self.builder.create_block();
+        let dest = self.blocks[ip as usize - 6].expect("BACK7 targets start a block");
+
+        let jump = self.builder.ins().icmp_imm(IntCC::SignedGreaterThan, lc, 0);
+        self.builder.ins().brif(jump, check, &[], cont, &[]);
+
+        // The flag is written by another thread, the load must not be hoisted
+        self.builder.switch_to_block(check);
+        self.build_count(self.regs.taken);
+        let flag = self
+            .builder
+            .ins()
+            .atomic_load(types::I8, MemFlags::trusted(), self.regs.interrupt_ptr);
+        self.builder.ins().brif(flag, interrupted, &[], dest, &[]);
+
+        // Stop at the loop header, as if the VM was interrupted right after the BACK7
+        self.builder.switch_to_block(interrupted);
+        self.build_exit(ip - 6, STATUS_INTERRUPTED);
+
+        self.builder.switch_to_block(cont);
+        self.build_count(self.regs.not_taken);
+    }
+}
+
+/// Native code of a program generated by Cranelift, owning the module holding it.
+pub struct CraneliftCode {
+    function: RunFunc,
+    // Only taken to free the memory of the function on drop, boxed as it is large
+    module: Option<Box<JITModule>>,
+    compile_time: Duration,
+    verify_time: Duration,
+}
+
+// The module is only touched while compiling, before the code can be shared, and
+// when it is dropped. The compiled function only accesses the registers it is
+// given and can be called from any thread.
+unsafe impl Send for CraneliftCode {}
+unsafe impl Sync for CraneliftCode {}
+
+impl CraneliftCode {
+    /// Compiles the program of `vm`, following its overflow mode.
+    pub fn compile<O: ExecutionObserver>(vm: &mut VM<O>, opt_level: OptLevel) -> Result<Self, VmError> {
+        jitted::check_program(vm)?;
+
+        let (ip, acc, lc) = (vm.state.ip, vm.state.acc, vm.state.lc);
+        let compilation_error = |message: String| VmError::JitCompilation { message, ip, acc, lc };
+
+        let mut flags = settings::builder();
+        flags
+            .set("opt_level", &opt_level.to_string())
+            .map_err(|e| compilation_error(e.to_string()))?;
+        // The function is verified on its own, to measure it as with LLVM
+        flags
+            .set("enable_verifier", "false")
+            .map_err(|e| compilation_error(e.to_string()))?;
+        let isa = cranelift_native::builder()
+            .map_err(|e| compilation_error(e.to_string()))?
+            .finish(settings::Flags::new(flags))
+            .map_err(|e| compilation_error(e.to_string()))?;
+
+        let mut module = JITModule::new(JITBuilder::with_isa(isa, default_libcall_names()));
+        let mut context = module.make_context();
+
+        let start = Instant::now();
+        let pointer = module.target_config().pointer_type();
+        context.func.signature.params = vec![AbiParam::new(pointer); 7];
+        context.func.signature.returns = vec![AbiParam::new(types::I32)];
+        let mut builder_context = FunctionBuilderContext::new();
+        translate(
+            &vm.running_program.data,
+            &vm.running_program.build_basic_blocks(),
+            vm.overflow_mode,
+            FunctionBuilder::new(&mut context.func, &mut builder_context),
+        );
+        let translate_time = start.elapsed();
+
+        let start = Instant::now();
+        verify_function(&context.func, module.isa()).map_err(|errors| VmError::JitVerification {
+            message: errors.to_string(),
+            ip,
+            acc,
+            lc,
+        })?;
+        let verify_time = start.elapsed();
+
+        let start = Instant::now();
+        let id = module
+            .declare_function(FUNC_NAME, Linkage::Export, &context.func.signature)
+            .map_err(|e| compilation_error(e.to_string()))?;
+        module
+            .define_function(id, &mut context)
+            .map_err(|e| compilation_error(e.to_string()))?;
+        module.clear_context(&mut context);
+        module
+            .finalize_definitions()
+            .map_err(|e| compilation_error(e.to_string()))?;
+        // SAFETY: the function was declared with the signature of `RunFunc`
+        let function = unsafe { std::mem::transmute::<*const u8, RunFunc>(module.get_finalized_function(id)) };
+        let compile_time = translate_time + start.elapsed();
+
+        Ok(Self {
+            function,
+            module: Some(Box::new(module)),
+            compile_time,
+            verify_time,
+        })
+    }
+
+    pub fn execute<O: ExecutionObserver>(&self, vm: &mut VM<O>) -> Result<ExecutionOutcome, VmError> {
+        jitted::execute(self.function, vm)
+    }
+
+    /// Time spent building the function and generating native code.
+    pub fn compile_time(&self) -> Duration {
+        self.compile_time
+    }
+
+    pub fn verify_time(&self) -> Duration {
+        self.verify_time
+    }
+}
+
+impl Drop for CraneliftCode {
+    fn drop(&mut self) {
+        if let Some(module) = self.module.take() {
+            // SAFETY: the function is not called once the code is dropped
+            unsafe { module.free_memory() };
+        }
+    }
+}
+
+/// Builds the function running `data`, which `check_program` accepted, from its blocks.
+fn translate(data: &[u8], basic_blocks: &[Vec<u8>], overflow_mode: OverflowMode, mut builder: FunctionBuilder) {
+    let (halt, spill): (u8, u8) = (OpCode::HALT.into(), OpCode::SPILL.into());
+
+    let entry = builder.create_block();
+    builder.append_block_params_for_function_params(entry);
+    builder.switch_to_block(entry);
+    let params = builder.block_params(entry).to_vec();
+
+    let (acc, lc, fuel, taken, not_taken) =
+        (Variable::from_u32(0), Variable::from_u32(1), Variable::from_u32(2), Variable::from_u32(3), Variable::from_u32(4));
+    builder.declare_var(acc, types::I32);
+    builder.declare_var(lc, types::I32);
+    builder.declare_var(fuel, types::I64);
+    builder.declare_var(taken, types::I64);
+    builder.declare_var(not_taken, types::I64);
+    let regs = Registers {
+        acc_ptr: params[0],
+        lc_ptr: params[1],
+        ip_ptr: params[2],
+        fuel_ptr: params[3],
+        taken_ptr: params[4],
+        not_taken_ptr: params[5],
+        interrupt_ptr: params[6],
+        acc,
+        lc,
+        fuel,
+        taken,
+        not_taken,
+    };
+
+    let flags = MemFlags::trusted();
+    for (var, ptr, ty) in [
+        (acc, regs.acc_ptr, types::I32),
+        (lc, regs.lc_ptr, types::I32),
+        (fuel, regs.fuel_ptr, types::I64),
+        (taken, regs.taken_ptr, types::I64),
+        (not_taken, regs.not_taken_ptr, types::I64),
+    ] {
+        let value = builder.ins().load(ty, flags, ptr, 0);
+        builder.def_var(var, value);
+    }
+
+    // One block per basic block of the program, blocks holding only a SPILL share
+    // the block of the next one
+    let mut blocks = vec![None; data.len()];
+    let mut ip = 0;
+    for basic_block in basic_blocks {
+        let len = basic_block.iter().filter(|instr| **instr != spill).count();
+        if len > 0 {
+            blocks[ip] = Some(builder.create_block());
+        }
+        ip += len;
+    }
+
+    // Jump to the block starting at the IP given by the caller. The registers are
+    // left untouched if there is no block at IP.
+    let no_entry = builder.create_block();
+    let ip_value = builder.ins().load(types::I32, flags, regs.ip_ptr, 0);
+    let mut switch = Switch::new();
+    for (ip, block) in blocks.iter().enumerate() {
+        if let Some(block) = block {
+            switch.set_entry(ip as u128, *block);
+        }
+    }
+    switch.emit(&mut builder, ip_value, no_entry);
+
+    builder.switch_to_block(no_entry);
+    let status = builder.ins().iconst(types::I32, STATUS_NO_ENTRY as i64);
+    builder.ins().return_(&[status]);
+
+    let mut translator = Translator {
+        builder,
+        regs: &regs,
+        overflow_mode,
+        blocks: &blocks,
+    };
+
+    let mut ip = 0u32;
+    let mut falls_through = false;
+    for basic_block in basic_blocks {
+        let instructions: Vec<u8> = basic_block.iter().copied().filter(|instr| *instr != spill).collect();
+        if instructions.is_empty() {
+            continue;
+        }
+        let block = blocks[ip as usize].expect("every basic block has a block");
+        if falls_through {
+            translator.builder.ins().jump(block, &[]);
+        }
+        translator.builder.switch_to_block(block);
+
+        // A block stops retiring instructions at the first HALT
+        let start = ip;
+        let cost = match instructions.iter().position(|instr| *instr == halt) {
+            Some(halt) => halt + 1,
+            None => instructions.len(),
+        } as u64;
+        translator.begin_block(start, cost);
+
+        falls_through = true;
+        for instr in &instructions[..cost as usize] {
+            let unretired = cost - (ip - start) as u64;
+            match OpCode::try_from(*instr).expect("the program was checked") {
+                OpCode::HALT => {
+                    translator.build_exit(ip, STATUS_HALT);
+                    falls_through = false;
+                }
+                OpCode::CLRA => {
+                    let zero = translator.builder.ins().iconst(types::I32, 0);
+                    translator.builder.def_var(acc, zero);
+                }
+                opcode @ (OpCode::INC3A | OpCode::DECA) => {
+                    let delta = if opcode == OpCode::INC3A { 3 } else { -1 };
+                    let value = translator.builder.use_var(acc);
+                    let value = translator.build_add(value, delta, ip, unretired);
+                    translator.builder.def_var(acc, value);
+                }
+                OpCode::SETL => {
+                    let value = translator.builder.use_var(acc);
+                    translator.builder.def_var(lc, value);
+                }
+                OpCode::BACK7 => translator.build_back7(ip, unretired),
+                OpCode::SPILL => unreachable!("SPILL instructions were filtered out"),
+            }
+            ip += 1;
+        }
+        // Instructions after a HALT are never executed
+        ip = start + instructions.len() as u32;
+    }
+
+    translator.builder.seal_all_blocks();
+    translator.builder.finalize();
+}
@@ -0,0 +1,158 @@
+//! Interpreter running a program decoded ahead of the run.
+//!
+//! [`SimpleInterpreter`](super::simple::SimpleInterpreter) decodes the byte at IP
+//! on every step, and reads and writes the registers through the VM. Here the
+//! program is decoded once into an array of [`Instr`], with the target of every
+//! BACK7 resolved, and the dispatch loop keeps the registers in locals until the
+//! run stops.
+
+use std::time::Duration;
+
+use crate::{
+    measure_time,
+    vm::{error::VmError, observer::ExecutionObserver, opcode::OpCode, program::Program, ExecutionOutcome, VM},
+};
+
+/// Instruction of a decoded program. The faults the simple interpreter detects
+/// are decoded too, so that they are only reported if the run reaches them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Instr {
+    Halt,
+    Clra,
+    Inc3a,
+    Deca,
+    Setl,
+    Back7 { target: u32 },
+    /// BACK7 jumping before the start of the program
+    Back7Underflow,
+    /// Byte which is not a user opcode
+    Invalid(u8),
+    /// End of the program, reached by running past its last byte
+    End,
+}
+
+impl Instr {
+    pub(super) fn decode(ip: u32, instr: u8) -> Self {
+        match OpCode::try_from(instr) {
+            Ok(OpCode::HALT) => Instr::Halt,
+            Ok(OpCode::CLRA) => Instr::Clra,
+            Ok(OpCode::INC3A) => Instr::Inc3a,
+            Ok(OpCode::DECA) => Instr::Deca,
+            Ok(OpCode::SETL) => Instr::Setl,
+            Ok(OpCode::BACK7) => match ip.checked_sub(6) {
+                Some(target) => Instr::Back7 { target },
+                None => Instr::Back7Underflow,
+            },
+            Ok(OpCode::SPILL) | Err(_) => Instr::Invalid(instr),
+        }
+    }
+}
+
+pub struct DecodedProgram {
+    code: Box<[Instr]>,
+    decode_time: Duration,
+}
+
+impl DecodedProgram {
+    pub fn decode(program: &Program) -> Self {
+        let mut code = Vec::with_capacity(program.data.len() + 1);
+        let decode_time = measure_time!({
+            for (index, instr) in program.data.iter().enumerate() {
+                code.push(Instr::decode(index as u32, *instr));
+            }
+            code.push(Instr::End);
+        });
+
+        Self {
+            code: code.into_boxed_slice(),
+            decode_time,
+        }
+    }
+
+    pub fn decode_time(&self) -> Duration {
+        self.decode_time
+    }
+
+    pub fn execute<O: ExecutionObserver>(&self, vm: &mut VM<O>) -> Result<ExecutionOutcome, VmError> {
+        super::drive(vm, |vm| self.dispatch(vm).map(|()| false))
+    }
+
+    /// Runs until HALT, a fault, the end of the fuel, or a taken BACK7 seeing a
+    /// pending interrupt, then writes the registers and counters back to `vm`.
+    fn dispatch<O: ExecutionObserver>(&self, vm: &mut VM<O>) -> Result<(), VmError> {
+        let overflow_mode = vm.overflow_mode;
+        let mut ip = vm.state.ip;
+        let mut acc = vm.state.acc;
+        let mut lc = vm.state.lc;
+        let mut fuel = vm.fuel.unwrap_or(u64::MAX);
+        let mut taken = 0;
+        let mut not_taken = 0;
+
+        let result = loop {
+            if fuel == 0 {
+                break Ok(());
+            }
+
+            let instr = self.code[ip as usize];
+            match instr {
+                Instr::Halt => {
+                    vm.state.halted = true;
+                    fuel -= 1;
+                    break Ok(());
+                }
+                Instr::Clra => acc = 0,
+                Instr::Inc3a => match overflow_mode.add(acc, 3) {
+                    Some(value) => acc = value,
+                    None => break Err(VmError::ArithmeticOverflow { ip, acc, lc }),
+                },
+                Instr::Deca => match overflow_mode.add(acc, -1) {
+                    Some(value) => acc = value,
+                    None => break Err(VmError::ArithmeticOverflow { ip, acc, lc }),
+                },
+                Instr::Setl => lc = acc,
+                Instr::Back7 { target } => match overflow_mode.add(lc, -1) {
+                    Some(value) if value > 0 => {
+                        lc = value;
+                        ip = target;
+                        taken += 1;
+                        fuel -= 1;
+                        if vm.interrupt.is_interrupted() {
+                            break Ok(());
+                        }
+                        continue;
+                    }
+                    Some(value) => {
+                        lc = value;
+                        not_taken += 1;
+                    }
+                    None => break Err(VmError::ArithmeticOverflow { ip, acc, lc }),
+                },
+                Instr::Back7Underflow => match overflow_mode.add(lc, -1) {
+                    Some(value) if value > 0 => break Err(VmError::IpUnderflow { ip, acc, lc }),
+                    Some(value) => {
+                        lc = value;
+                        not_taken += 1;
+                    }
+                    None => break Err(VmError::ArithmeticOverflow { ip, acc, lc }),
+                },
+                Instr::Invalid(opcode) => break Err(VmError::InvalidOpcode { opcode, ip, acc, lc }),
+                Instr::End => break Err(VmError::IpOutOfBounds { ip, acc, lc }),
+            }
+
+            ip += 1;
+            fuel -= 1;
+        };
+
+        vm.state.ip = ip;
+        vm.state.acc = acc;
+        vm.state.lc = lc;
+        vm.back_edges_taken += taken;
+        vm.back_edges_not_taken += not_taken;
+        vm.instructions += vm.fuel.unwrap_or(u64::MAX) - fuel;
+        if vm.fuel.is_some() {
+            vm.fuel = Some(fuel);
+        }
+
+        result
+    }
+}
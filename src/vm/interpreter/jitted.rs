@@ -1,4 +1,7 @@
-use std::cell::{Cell, RefCell};
+use std::{
+    cell::{Cell, RefCell},
+    time::{Duration, Instant},
+};
 
 use inkwell::{
     basic_block::BasicBlock,
@@ -6,32 +9,219 @@ use inkwell::{
     context::Context,
     execution_engine::{ExecutionEngine, JitFunction},
     module::Module,
-    values::{FunctionValue, PointerValue},
-    AddressSpace, OptimizationLevel,
+    values::{FunctionValue, IntValue, PointerValue},
+    AddressSpace, AtomicOrdering, IntPredicate, OptimizationLevel,
 };
 
-use crate::{
-    measure_time,
-    vm::{opcode::OpCode, VM},
+use crate::vm::{
+    error::VmError, observer::ExecutionObserver, opcode::OpCode, ExecutionOutcome, OverflowMode, VM,
 };
 
 use super::Interpreter;
 
-pub type RunFunc = unsafe extern "C" fn(*mut i32, *mut i32);
+/// Takes pointers to ACC, LC, IP, the remaining fuel and the counts of taken and not
+/// taken BACK7, updated on exit, and to the interrupt flag. Returns one of the
+/// `STATUS_*` codes. The execution starts from the block beginning at IP.
+pub type RunFunc =
+    unsafe extern "C" fn(*mut i32, *mut i32, *mut u32, *mut u64, *mut u64, *mut u64, *const u8) -> u32;
 
 const MOD_NAME: &str = "vmt_vm_mod";
 const FUNC_NAME: &str = "vt_vm";
 
+pub(super) const STATUS_HALT: u32 = 0;
+pub(super) const STATUS_OVERFLOW: u32 = 1;
+// The next block needs more fuel than what is left
+pub(super) const STATUS_OUT_OF_FUEL: u32 = 2;
+// No block starts at the IP given on entry
+pub(super) const STATUS_NO_ENTRY: u32 = 3;
+// The interrupt flag was found set after a BACK7 jumped back
+pub(super) const STATUS_INTERRUPTED: u32 = 4;
+
 struct FunctionContext<'ctx> {
     function: FunctionValue<'ctx>,
+    entry: BasicBlock<'ctx>,
     acc: PointerValue<'ctx>,
     acc_ptr: PointerValue<'ctx>,
     lc: PointerValue<'ctx>,
     lc_ptr: PointerValue<'ctx>,
+    ip_ptr: PointerValue<'ctx>,
+    fuel: PointerValue<'ctx>,
+    fuel_ptr: PointerValue<'ctx>,
+    taken: PointerValue<'ctx>,
+    taken_ptr: PointerValue<'ctx>,
+    not_taken: PointerValue<'ctx>,
+    not_taken_ptr: PointerValue<'ctx>,
+    interrupt_ptr: PointerValue<'ctx>,
+    // Blocks stop when the fuel left cannot cover them
+    check_fuel: bool,
+    // IP of the instruction being translated
+    ip: Cell<u32>,
+    // Instructions of the block charged to the fuel but not retired before the one being translated
+    unretired: Cell<u64>,
+    // Blocks the function can be entered from, with the IP of their first instruction
+    entries: Vec<(u32, BasicBlock<'ctx>)>,
     spilled_cnt: Cell<Option<usize>>,
     spilled_bbs: Vec<BasicBlock<'ctx>>,
 }
 
+/// Native code of a program, owning the LLVM context it was compiled in.
+pub struct JitCode {
+    // Fields are dropped in order, the context must outlive everything built from it
+    function: JitFunction<'static, RunFunc>,
+    check_fuel: bool,
+    compile_time: Duration,
+    verify_time: Duration,
+    _interpreter: JittedInterpreter<'static>,
+    _context: Box<Context>,
+}
+
+// The context, the module and the execution engine are only touched while compiling,
+// before the code can be shared, and when the last owner drops it. The compiled
+// function only accesses the registers it is given and can be called from any thread.
+unsafe impl Send for JitCode {}
+unsafe impl Sync for JitCode {}
+
+impl JitCode {
+    /// Compiles the program of `vm`, following its overflow mode. The code only checks
+    /// the fuel if `vm` runs with a fuel limit.
+    pub fn compile<O: ExecutionObserver>(vm: &mut VM<O>, opt_level: OptimizationLevel) -> Result<Self, VmError> {
+        let context = Box::new(Context::create());
+        // SAFETY: the context is boxed, so it does not move with `JitCode`, and it is
+        // dropped after the interpreter and the function borrowing it.
+        let context_ref: &'static Context = unsafe { &*(context.as_ref() as *const Context) };
+
+        let interpreter = JittedInterpreter::new(context_ref, opt_level).map_err(|message| {
+            VmError::JitCompilation {
+                message,
+                ip: vm.state.ip,
+                acc: vm.state.acc,
+                lc: vm.state.lc,
+            }
+        })?;
+
+        // LLVM generates the native code when the function is first looked up
+        let start = Instant::now();
+        interpreter.translate(vm)?;
+        let translate_time = start.elapsed();
+
+        let start = Instant::now();
+        interpreter.verify(vm)?;
+        let verify_time = start.elapsed();
+
+        let start = Instant::now();
+        let function = interpreter.function(vm)?;
+        let compile_time = translate_time + start.elapsed();
+
+        Ok(Self {
+            function,
+            check_fuel: vm.fuel.is_some(),
+            compile_time,
+            verify_time,
+            _interpreter: interpreter,
+            _context: context,
+        })
+    }
+
+    pub fn execute<O: ExecutionObserver>(&self, vm: &mut VM<O>) -> Result<ExecutionOutcome, VmError> {
+        // SAFETY: the function is called while `self` owns the code
+        execute(unsafe { self.function.as_raw() }, vm)
+    }
+
+    /// True if the code stops when the fuel runs out, so that it can run VMs with a fuel limit.
+    pub fn check_fuel(&self) -> bool {
+        self.check_fuel
+    }
+
+    /// Time spent building the module and generating native code.
+    pub fn compile_time(&self) -> Duration {
+        self.compile_time
+    }
+
+    pub fn verify_time(&self) -> Duration {
+        self.verify_time
+    }
+}
+
+/// The JITs translate the whole program ahead of time, so the faults the simple
+/// interpreter detects while running have to be rejected before generating code.
+pub(super) fn check_program<O: ExecutionObserver>(vm: &VM<O>) -> Result<(), VmError> {
+    let acc = vm.state.acc;
+    let lc = vm.state.lc;
+    let data = &vm.running_program.data;
+
+    for (index, instr) in data.iter().enumerate() {
+        let ip = index as u32;
+        match OpCode::try_from(*instr) {
+            Ok(OpCode::SPILL) | Err(_) => {
+                return Err(VmError::InvalidOpcode { opcode: *instr, ip, acc, lc })
+            }
+            Ok(OpCode::BACK7) if index < 6 => return Err(VmError::IpUnderflow { ip, acc, lc }),
+            _ => (),
+        }
+    }
+
+    // Without a trailing HALT the generated code would fall off the end of the function
+    if data.last() != Some(&OpCode::HALT.into()) {
+        return Err(VmError::IpOutOfBounds { ip: data.len() as u32, acc, lc });
+    }
+
+    Ok(())
+}
+
+/// Runs `vm` with `run`, the native code of its program, until it halts or stops.
+pub(super) fn execute<O: ExecutionObserver>(run: RunFunc, vm: &mut VM<O>) -> Result<ExecutionOutcome, VmError> {
+    super::drive(vm, |vm| {
+        let status = unsafe {
+            let mut acc = vm.state.acc;
+            let mut lc = vm.state.lc;
+            let mut ip = vm.state.ip;
+            let mut fuel = vm.fuel.unwrap_or(u64::MAX);
+            let mut taken = vm.back_edges_taken;
+            let mut not_taken = vm.back_edges_not_taken;
+
+            // Call the compiled-in-memory function
+            let status = run(
+                &mut acc as *mut i32,
+                &mut lc as *mut i32,
+                &mut ip as *mut u32,
+                &mut fuel as *mut u64,
+                &mut taken as *mut u64,
+                &mut not_taken as *mut u64,
+                vm.interrupt.as_ptr(),
+            );
+
+            vm.state.acc = acc;
+            vm.state.lc = lc;
+            vm.state.ip = ip;
+            vm.back_edges_taken = taken;
+            vm.back_edges_not_taken = not_taken;
+            vm.instructions += vm.fuel.unwrap_or(u64::MAX) - fuel;
+            if vm.fuel.is_some() {
+                vm.fuel = Some(fuel);
+            }
+            status
+        };
+
+        match status {
+            STATUS_HALT => {
+                vm.state.halted = true;
+                Ok(false)
+            }
+            // The request is taken at the top of the loop
+            STATUS_INTERRUPTED => Ok(false),
+            STATUS_OVERFLOW => Err(VmError::ArithmeticOverflow {
+                ip: vm.state.ip,
+                acc: vm.state.acc,
+                lc: vm.state.lc,
+            }),
+            // The VM stopped at a block the remaining fuel cannot cover, or it was
+            // resumed in the middle of a block: interpret a single instruction and
+            // retry, the interpreter accounts for the fuel one instruction at a time
+            _ => Ok(true),
+        }
+    })
+}
+
 pub struct JittedInterpreter<'ctx> {
     module: Module<'ctx>,
     builder: Builder<'ctx>,
@@ -40,50 +230,90 @@ pub struct JittedInterpreter<'ctx> {
 }
 
 impl<'ctx> JittedInterpreter<'ctx> {
-    pub fn new(context: &'ctx Context, opt_level: OptimizationLevel) -> Self {
+    pub fn new(context: &'ctx Context, opt_level: OptimizationLevel) -> Result<Self, String> {
         let module = context.create_module(MOD_NAME);
-        let execution_engine = module.create_jit_execution_engine(opt_level).unwrap();
+        let execution_engine = module
+            .create_jit_execution_engine(opt_level)
+            .map_err(|msg| msg.to_string())?;
         let builder = module.get_context().create_builder();
 
-        Self {
+        Ok(Self {
             module,
             execution_engine,
             builder,
             fun_context: RefCell::new(None),
-        }
+        })
     }
 
-    fn setup_jit_function(&self) {
+    /// Builds the entry of the function. Every block consumes the fuel of all its
+    /// instructions, so that the fuel consumed by a call gives the number of instructions
+    /// it retired. With `check_fuel`, blocks first check that the remaining fuel covers them.
+    fn setup_jit_function(&self, check_fuel: bool) {
         let i32_type = self.module.get_context().i32_type();
+        let i64_type = self.module.get_context().i64_type();
         let i32ptr_type = self
             .module
             .get_context()
             .i32_type()
             .ptr_type(AddressSpace::Generic);
-        let fun_type = self
+        let i64ptr_type = i64_type.ptr_type(AddressSpace::Generic);
+        let i8ptr_type = self
             .module
             .get_context()
-            .void_type()
-            .fn_type(&[i32ptr_type.into(), i32ptr_type.into()], false);
+            .i8_type()
+            .ptr_type(AddressSpace::Generic);
+        let fun_type = self.module.get_context().i32_type().fn_type(
+            &[
+                i32ptr_type.into(),
+                i32ptr_type.into(),
+                i32ptr_type.into(),
+                i64ptr_type.into(),
+                i64ptr_type.into(),
+                i64ptr_type.into(),
+                i8ptr_type.into(),
+            ],
+            false,
+        );
         let function = self.module.add_function(FUNC_NAME, fun_type, None);
 
-        let basic_block = self
+        let entry = self
             .module
             .get_context()
             .append_basic_block(function, "entry");
-        self.builder.position_at_end(basic_block);
+        self.builder.position_at_end(entry);
 
         let acc_ptr = self.builder.build_alloca(i32ptr_type, "");
         let lc_ptr = self.builder.build_alloca(i32ptr_type, "");
+        let ip_ptr = self.builder.build_alloca(i32ptr_type, "");
+        let fuel_ptr = self.builder.build_alloca(i64ptr_type, "");
+        let taken_ptr = self.builder.build_alloca(i64ptr_type, "");
+        let not_taken_ptr = self.builder.build_alloca(i64ptr_type, "");
+        let interrupt_ptr = self.builder.build_alloca(i8ptr_type, "");
 
         let acc = self.builder.build_alloca(i32_type, "acc");
         let lc = self.builder.build_alloca(i32_type, "lc");
+        let fuel = self.builder.build_alloca(i64_type, "fuel");
+        let taken = self.builder.build_alloca(i64_type, "taken");
+        let not_taken = self.builder.build_alloca(i64_type, "not_taken");
         self.fun_context.replace(Some(FunctionContext {
             function,
+            entry,
             acc,
             acc_ptr,
             lc,
             lc_ptr,
+            ip_ptr,
+            fuel,
+            fuel_ptr,
+            taken,
+            taken_ptr,
+            not_taken,
+            not_taken_ptr,
+            interrupt_ptr,
+            check_fuel,
+            ip: Cell::new(0),
+            unretired: Cell::new(0),
+            entries: vec![],
             spilled_bbs: vec![],
             spilled_cnt: Cell::new(None),
         }));
@@ -99,9 +329,39 @@ impl<'ctx> JittedInterpreter<'ctx> {
                 .get_nth_param(1)
                 .unwrap()
                 .into_pointer_value();
+            let third_param = fun_context
+                .function
+                .get_nth_param(2)
+                .unwrap()
+                .into_pointer_value();
+            let fourth_param = fun_context
+                .function
+                .get_nth_param(3)
+                .unwrap()
+                .into_pointer_value();
+            let fifth_param = fun_context
+                .function
+                .get_nth_param(4)
+                .unwrap()
+                .into_pointer_value();
+            let sixth_param = fun_context
+                .function
+                .get_nth_param(5)
+                .unwrap()
+                .into_pointer_value();
+            let seventh_param = fun_context
+                .function
+                .get_nth_param(6)
+                .unwrap()
+                .into_pointer_value();
 
             self.builder.build_store(acc_ptr, first_param);
             self.builder.build_store(lc_ptr, second_param);
+            self.builder.build_store(ip_ptr, third_param);
+            self.builder.build_store(fuel_ptr, fourth_param);
+            self.builder.build_store(taken_ptr, fifth_param);
+            self.builder.build_store(not_taken_ptr, sixth_param);
+            self.builder.build_store(interrupt_ptr, seventh_param);
 
             let acc_ptr = self.builder.build_load(acc_ptr, "");
             let acc_ptr_val = self.builder.build_load(acc_ptr.into_pointer_value(), "");
@@ -111,47 +371,283 @@ impl<'ctx> JittedInterpreter<'ctx> {
             let lc_ptr_val = self.builder.build_load(lc_ptr.into_pointer_value(), "");
             self.builder.build_store(lc, lc_ptr_val);
 
+            let fuel_ptr = self.builder.build_load(fuel_ptr, "");
+            let fuel_ptr_val = self.builder.build_load(fuel_ptr.into_pointer_value(), "");
+            self.builder.build_store(fun_context.fuel, fuel_ptr_val);
+
+            let taken_ptr = self.builder.build_load(taken_ptr, "");
+            let taken_ptr_val = self.builder.build_load(taken_ptr.into_pointer_value(), "");
+            self.builder.build_store(taken, taken_ptr_val);
+
+            let not_taken_ptr = self.builder.build_load(not_taken_ptr, "");
+            let not_taken_ptr_val = self.builder.build_load(not_taken_ptr.into_pointer_value(), "");
+            self.builder.build_store(not_taken, not_taken_ptr_val);
+
+            // The entry block is terminated by `build_entry_switch` once all the blocks exist
             let basic_block = self.module.get_context().append_basic_block(function, "bb");
-            self.builder.build_unconditional_branch(basic_block);
             self.builder.position_at_end(basic_block);
         }
     }
 
-    fn jit_compile(&self) -> Option<JitFunction<RunFunc>> {
+    /// Marks the start of a block of the program, at which the function can be entered.
+    /// `cost` is the number of instructions the block retires when executed.
+    fn begin_block(&self, ip: u32, cost: u64) {
+        if let Some(fun_context) = self.fun_context.borrow_mut().as_mut() {
+            let current_bb = fun_context.function.get_last_basic_block().unwrap();
+
+            // Blocks holding only a SPILL share the IP of the next block
+            if fun_context.entries.last().map(|(entry_ip, _)| *entry_ip) != Some(ip) {
+                fun_context.entries.push((ip, current_bb));
+            }
+
+            // Without checks, the fuel starts at `u64::MAX` and only counts the retired instructions
+            if cost > 0 {
+                let fuel = fun_context.fuel;
+                let cost = self.module.get_context().i64_type().const_int(cost, false);
+                let fuel_value = self.builder.build_load(fuel, "").into_int_value();
+                let fuel_left = self.builder.build_int_sub(fuel_value, cost, "");
+                if !fun_context.check_fuel {
+                    self.builder.build_store(fuel, fuel_left);
+                    return;
+                }
+
+                let exhausted = self.builder.build_int_compare(
+                    IntPredicate::ULT,
+                    fuel_value,
+                    cost,
+                    "",
+                );
+
+                // Append the exit block first, the continuation must stay the last block
+                let exit_bb = self
+                    .module
+                    .get_context()
+                    .append_basic_block(fun_context.function, "fuel.bb");
+                let cont_bb = self
+                    .module
+                    .get_context()
+                    .append_basic_block(fun_context.function, "bb");
+                self.builder
+                    .build_conditional_branch(exhausted, exit_bb, cont_bb);
+
+                self.builder.position_at_end(exit_bb);
+                self.build_exit(fun_context, ip, STATUS_OUT_OF_FUEL);
+
+                self.builder.position_at_end(cont_bb);
+                self.builder.build_store(fuel, fuel_left);
+            }
+        }
+    }
+
+    /// Terminates the entry block, jumping to the block starting at the IP given by the caller.
+    fn build_entry_switch(&self) {
+        if let Some(fun_context) = self.fun_context.borrow().as_ref() {
+            let i32_type = self.module.get_context().i32_type();
+
+            // The registers are left untouched if there is no block at IP
+            let no_entry_bb = self
+                .module
+                .get_context()
+                .append_basic_block(fun_context.function, "no_entry.bb");
+            self.builder.position_at_end(no_entry_bb);
+            let status = i32_type.const_int(STATUS_NO_ENTRY as u64, false);
+            self.builder.build_return(Some(&status));
+
+            self.builder.position_at_end(fun_context.entry);
+            let ip_ptr = self.builder.build_load(fun_context.ip_ptr, "");
+            let ip_value = self.builder.build_load(ip_ptr.into_pointer_value(), "");
+
+            let cases: Vec<_> = fun_context
+                .entries
+                .iter()
+                .map(|(ip, bb)| (i32_type.const_int(*ip as u64, false), *bb))
+                .collect();
+            self.builder
+                .build_switch(ip_value.into_int_value(), no_entry_bb, &cases);
+        }
+    }
+
+    /// Writes the registers back through the function parameters and returns `status`.
+    fn build_exit(&self, fun_context: &FunctionContext<'ctx>, ip: u32, status: u32) {
+        let i32_type = self.module.get_context().i32_type();
+
+        let acc_value = self.builder.build_load(fun_context.acc, "");
+        let acc_ptr = self.builder.build_load(fun_context.acc_ptr, "");
+        self.builder
+            .build_store(acc_ptr.into_pointer_value(), acc_value);
+
+        let lc_value = self.builder.build_load(fun_context.lc, "");
+        let lc_ptr = self.builder.build_load(fun_context.lc_ptr, "");
+        self.builder
+            .build_store(lc_ptr.into_pointer_value(), lc_value);
+
+        let ip_value = i32_type.const_int(ip as u64, false);
+        let ip_ptr = self.builder.build_load(fun_context.ip_ptr, "");
+        self.builder
+            .build_store(ip_ptr.into_pointer_value(), ip_value);
+
+        let fuel_value = self.builder.build_load(fun_context.fuel, "");
+        let fuel_ptr = self.builder.build_load(fun_context.fuel_ptr, "");
+        self.builder
+            .build_store(fuel_ptr.into_pointer_value(), fuel_value);
+
+        let taken_value = self.builder.build_load(fun_context.taken, "");
+        let taken_ptr = self.builder.build_load(fun_context.taken_ptr, "");
+        self.builder
+            .build_store(taken_ptr.into_pointer_value(), taken_value);
+
+        let not_taken_value = self.builder.build_load(fun_context.not_taken, "");
+        let not_taken_ptr = self.builder.build_load(fun_context.not_taken_ptr, "");
+        self.builder
+            .build_store(not_taken_ptr.into_pointer_value(), not_taken_value);
+
+        let status = i32_type.const_int(status as u64, false);
+        self.builder.build_return(Some(&status));
+    }
+
+    /// Builds `value + delta` following the overflow mode of the VM, so that the
+    /// compiled code behaves exactly as the simple interpreter. A trap gives back
+    /// the fuel of the `unretired` instructions of the block.
+    fn build_add(
+        &self,
+        fun_context: &FunctionContext<'ctx>,
+        overflow_mode: OverflowMode,
+        value: IntValue<'ctx>,
+        delta: i32,
+        unretired: u64,
+    ) -> IntValue<'ctx> {
+        let i32_type = self.module.get_context().i32_type();
+        let delta_value = i32_type.const_int(delta.unsigned_abs() as u64, false);
+
+        let wrapped = if delta >= 0 {
+            self.builder.build_int_add(value, delta_value, "")
+        } else {
+            self.builder.build_int_sub(value, delta_value, "")
+        };
+
+        if overflow_mode == OverflowMode::Wrapping {
+            return wrapped;
+        }
+
+        // The operand overflows if it is past the bound minus the delta
+        let (predicate, bound, limit) = if delta >= 0 {
+            (IntPredicate::SGT, i32::MAX - delta, i32::MAX)
+        } else {
+            (IntPredicate::SLT, i32::MIN - delta, i32::MIN)
+        };
+        let bound = i32_type.const_int(bound as u64, true);
+        let overflow = self.builder.build_int_compare(predicate, value, bound, "");
+
+        match overflow_mode {
+            OverflowMode::Saturating => {
+                let limit = i32_type.const_int(limit as u64, true);
+                self.builder
+                    .build_select(overflow, limit, wrapped, "")
+                    .into_int_value()
+            }
+            _ => {
+                // Append the trap block first, the continuation must stay the last block
+                let trap_bb = self
+                    .module
+                    .get_context()
+                    .append_basic_block(fun_context.function, "trap.bb");
+                let cont_bb = self
+                    .module
+                    .get_context()
+                    .append_basic_block(fun_context.function, "bb");
+                self.builder
+                    .build_conditional_branch(overflow, trap_bb, cont_bb);
+
+                self.builder.position_at_end(trap_bb);
+                let unretired = self.module.get_context().i64_type().const_int(unretired, false);
+                let fuel_value = self.builder.build_load(fun_context.fuel, "").into_int_value();
+                let fuel_value = self.builder.build_int_add(fuel_value, unretired, "");
+                self.builder.build_store(fun_context.fuel, fuel_value);
+                self.build_exit(fun_context, fun_context.ip.get(), STATUS_OVERFLOW);
+
+                self.builder.position_at_end(cont_bb);
+                wrapped
+            }
+        }
+    }
+
+    /// Increments the 64 bits counter at `counter`.
+    fn build_count(&self, counter: PointerValue<'ctx>) {
+        let one = self.module.get_context().i64_type().const_int(1, false);
+        let value = self.builder.build_load(counter, "").into_int_value();
+        let incremented = self.builder.build_int_add(value, one, "");
+        self.builder.build_store(counter, incremented);
+    }
+
+    fn jit_compile(&self) -> Option<JitFunction<'ctx, RunFunc>> {
         unsafe { self.execution_engine.get_function(FUNC_NAME).ok() }
     }
 }
 
-impl<'ctx> Interpreter for JittedInterpreter<'ctx> {
-    fn run(&self, vm: &VM) {
+impl<'ctx> JittedInterpreter<'ctx> {
+    /// Translates the program of `vm`, verifies the module and JIT compiles it. The
+    /// registers of `vm` are only used to report errors.
+    fn compile<O: ExecutionObserver>(&self, vm: &mut VM<O>) -> Result<JitFunction<'ctx, RunFunc>, VmError> {
+        self.translate(vm)?;
+        self.verify(vm)?;
+        self.function(vm)
+    }
+
+    /// Builds the LLVM function running the program of `vm`.
+    fn translate<O: ExecutionObserver>(&self, vm: &mut VM<O>) -> Result<(), VmError> {
+        check_program(vm)?;
+
         // Prepare function environment
-        self.setup_jit_function();
+        self.setup_jit_function(vm.fuel.is_some());
 
         let mut halt = false;
         let mut has_jump = false;
 
         let basic_blocks = vm.running_program.build_basic_blocks();
+        let mut ip = 0;
 
         for basic_block in basic_blocks.iter() {
+            // A block stops retiring instructions at the first HALT
+            let (spill, halt_instr): (u8, u8) = (OpCode::SPILL.into(), OpCode::HALT.into());
+            let instructions = basic_block.iter().filter(|instr| **instr != spill);
+            let cost = match instructions.clone().position(|instr| *instr == halt_instr) {
+                Some(halt) => halt + 1,
+                None => instructions.count(),
+            };
+            self.begin_block(ip, cost as u64);
+            let start = ip;
+
             // Build branch to next basic block
             for instr in basic_block.iter() {
                 // Write LLVM bitcode inside the function environment
                 let instr = *instr;
-                match OpCode::try_from(instr).unwrap() {
+                let opcode = OpCode::try_from(instr).unwrap();
+
+                // SPILL instructions are not part of the program and have no IP
+                if opcode != OpCode::SPILL {
+                    if let Some(fun_context) = self.fun_context.borrow().as_ref() {
+                        fun_context.ip.set(ip);
+                        // Instructions after a HALT are never executed
+                        fun_context.unretired.set((cost as u64).saturating_sub((ip - start) as u64));
+                    }
+                    ip += 1;
+                }
+
+                match opcode {
                     OpCode::HALT => {
-                        self.halt(vm, instr);
+                        self.halt(vm, instr)?;
                         halt = true;
                     }
-                    OpCode::CLRA => self.clra(vm, instr),
-                    OpCode::INC3A => self.inc3a(vm, instr),
-                    OpCode::DECA => self.deca(vm, instr),
-                    OpCode::SETL => self.setl(vm, instr),
+                    OpCode::CLRA => self.clra(vm, instr)?,
+                    OpCode::INC3A => self.inc3a(vm, instr)?,
+                    OpCode::DECA => self.deca(vm, instr)?,
+                    OpCode::SETL => self.setl(vm, instr)?,
                     OpCode::BACK7 => {
-                        self.back7(vm, instr);
+                        self.back7(vm, instr)?;
                         has_jump = true;
                     }
                     OpCode::SPILL => {
-                        self.spill(vm, instr);
+                        self.spill(vm, instr)?;
                         has_jump = true;
                     }
                 }
@@ -172,109 +668,103 @@ impl<'ctx> Interpreter for JittedInterpreter<'ctx> {
             }
         }
 
+        self.build_entry_switch();
+
         // Print LLVM module to the stderr
         // self.module.print_to_stderr();
 
-        // Verify the module's correctness before executing the result.
-        match self.module.verify() {
-            Ok(_) => (),
-            Err(msg) => panic!(
-                "Error while verifying LLVM module: {}",
-                msg.to_str().unwrap()
-            ),
-        }
-
-        // Run the compiled code
-        if let Some(fun) = self.jit_compile() {
-            let elapsed_time = measure_time!({
-                unsafe {
-                    let mut acc = vm.registers.acc_value();
-                    let mut lc = vm.registers.lc_value();
-
-                    // Call the compiled-in-memory function
-                    fun.call(&mut acc as *mut i32, &mut lc as *mut i32);
+        Ok(())
+    }
 
-                    vm.registers.acc.replace(acc);
-                    vm.registers.lc.replace(lc);
-                }
+    /// Verifies the module's correctness before executing the result.
+    fn verify<O: ExecutionObserver>(&self, vm: &VM<O>) -> Result<(), VmError> {
+        if let Err(msg) = self.module.verify() {
+            return Err(VmError::JitVerification {
+                message: msg.to_string(),
+                ip: vm.state.ip,
+                acc: vm.state.acc,
+                lc: vm.state.lc,
             });
-            vm.running_time.replace(elapsed_time);
-        } else {
-            panic!("Unable to JIT compile VM code.")
         }
+
+        Ok(())
     }
 
-    fn halt(&self, _: &VM, _: u8) {
-        if let Some(fun_context) = self.fun_context.borrow().as_ref() {
-            let acc_value = self.builder.build_load(fun_context.acc, "");
-            let acc_ptr = self.builder.build_load(fun_context.acc_ptr, "");
-            self.builder
-                .build_store(acc_ptr.into_pointer_value(), acc_value);
+    /// Looks the function up in the execution engine, which compiles it.
+    fn function<O: ExecutionObserver>(&self, vm: &VM<O>) -> Result<JitFunction<'ctx, RunFunc>, VmError> {
+        self.jit_compile().ok_or_else(|| VmError::JitCompilation {
+            message: format!("function `{}` not found in the execution engine", FUNC_NAME),
+            ip: vm.state.ip,
+            acc: vm.state.acc,
+            lc: vm.state.lc,
+        })
+    }
+}
 
-            let lc_value = self.builder.build_load(fun_context.lc, "");
-            let lc_ptr = self.builder.build_load(fun_context.lc_ptr, "");
-            self.builder
-                .build_store(lc_ptr.into_pointer_value(), lc_value);
+impl<'ctx> Interpreter for JittedInterpreter<'ctx> {
+    fn run<O: ExecutionObserver>(&self, vm: &mut VM<O>) -> Result<ExecutionOutcome, VmError> {
+        let fun = self.compile(vm)?;
+        execute(unsafe { fun.as_raw() }, vm)
+    }
 
-            // Build return instruction
-            self.builder.build_return(None);
+    fn halt<O: ExecutionObserver>(&self, _: &mut VM<O>, _: u8) -> Result<(), VmError> {
+        if let Some(fun_context) = self.fun_context.borrow().as_ref() {
+            // Store the registers and build return instruction
+            self.build_exit(fun_context, fun_context.ip.get(), STATUS_HALT);
         }
+        Ok(())
     }
 
-    fn clra(&self, _: &VM, _: u8) {
+    fn clra<O: ExecutionObserver>(&self, _: &mut VM<O>, _: u8) -> Result<(), VmError> {
         if let Some(fun_context) = self.fun_context.borrow().as_ref() {
             let zero = self.module.get_context().i32_type().const_zero();
             self.builder.build_store(fun_context.acc, zero);
         }
+        Ok(())
     }
 
-    fn inc3a(&self, _: &VM, _: u8) {
+    fn inc3a<O: ExecutionObserver>(&self, vm: &mut VM<O>, _: u8) -> Result<(), VmError> {
         if let Some(fun_context) = self.fun_context.borrow().as_ref() {
-            let three = self.module.get_context().i32_type().const_int(3, false);
-
             let prev_value = self.builder.build_load(fun_context.acc, "");
 
-            let inc = self
-                .builder
-                .build_int_nsw_add(prev_value.into_int_value(), three, "");
+            let unretired = fun_context.unretired.get();
+            let inc = self.build_add(fun_context, vm.overflow_mode, prev_value.into_int_value(), 3, unretired);
 
             self.builder.build_store(fun_context.acc, inc);
         }
+        Ok(())
     }
 
-    fn deca(&self, _: &VM, _: u8) {
+    fn deca<O: ExecutionObserver>(&self, vm: &mut VM<O>, _: u8) -> Result<(), VmError> {
         if let Some(fun_context) = self.fun_context.borrow().as_ref() {
-            let one = self.module.get_context().i32_type().const_int(1, false);
-
             let prev_value = self.builder.build_load(fun_context.acc, "");
 
-            let dec = self
-                .builder
-                .build_int_nsw_sub(prev_value.into_int_value(), one, "");
+            let unretired = fun_context.unretired.get();
+            let dec = self.build_add(fun_context, vm.overflow_mode, prev_value.into_int_value(), -1, unretired);
 
             self.builder.build_store(fun_context.acc, dec);
         }
+        Ok(())
     }
 
-    fn setl(&self, _: &VM, _: u8) {
+    fn setl<O: ExecutionObserver>(&self, _: &mut VM<O>, _: u8) -> Result<(), VmError> {
         if let Some(fun_context) = self.fun_context.borrow().as_ref() {
             let acc_value = self.builder.build_load(fun_context.acc, "");
             self.builder.build_store(fun_context.lc, acc_value);
         }
+        Ok(())
     }
 
-    fn back7(&self, _: &VM, _: u8) {
+    fn back7<O: ExecutionObserver>(&self, vm: &mut VM<O>, _: u8) -> Result<(), VmError> {
         if let Some(fun_context) = self.fun_context.borrow_mut().as_mut() {
             // Get current basic block reference
             // let current_bb = fun_context.function.get_last_basic_block().unwrap();
             let zero = self.module.get_context().i32_type().const_int(0, false);
-            let one = self.module.get_context().i32_type().const_int(1, false);
 
             let lc_prev = self.builder.build_load(fun_context.lc, "");
 
-            let dec = self
-                .builder
-                .build_int_nsw_sub(lc_prev.into_int_value(), one, "");
+            let unretired = fun_context.unretired.get();
+            let dec = self.build_add(fun_context, vm.overflow_mode, lc_prev.into_int_value(), -1, unretired);
 
             self.builder.build_store(fun_context.lc, dec);
 
@@ -287,7 +777,22 @@ impl<'ctx> Interpreter for JittedInterpreter<'ctx> {
                 "",
             );
 
-            // Build branch
+            // Build branch, checking the interrupt flag when jumping back. The new
+            // blocks are appended before the continuation, which must stay the last one.
+            let check_bb = self
+                .module
+                .get_context()
+                .append_basic_block(fun_context.function, "interrupt.bb");
+            let exit_bb = self
+                .module
+                .get_context()
+                .append_basic_block(fun_context.function, "interrupted.bb");
+            // The fall-through is counted on its edge, as the continuation is also an
+            // entry of the function for the IP after the BACK7
+            let not_taken_bb = self
+                .module
+                .get_context()
+                .append_basic_block(fun_context.function, "not_taken.bb");
             let new_bb = self
                 .module
                 .get_context()
@@ -301,14 +806,43 @@ impl<'ctx> Interpreter for JittedInterpreter<'ctx> {
                 .replace(Some(fun_context.spilled_cnt.get().unwrap() + 1));
 
             self.builder
-                .build_conditional_branch(comparison, dest_bb, new_bb);
+                .build_conditional_branch(comparison, check_bb, not_taken_bb);
+
+            self.builder.position_at_end(check_bb);
+            self.build_count(fun_context.taken);
+
+            // The flag is an `AtomicBool` stored by another thread, read it atomically
+            let interrupt_ptr = self.builder.build_load(fun_context.interrupt_ptr, "");
+            let flag = self
+                .builder
+                .build_load(interrupt_ptr.into_pointer_value(), "");
+            if let Some(load) = flag.as_instruction_value() {
+                load.set_atomic_ordering(AtomicOrdering::Monotonic).unwrap();
+            }
+            let interrupted = self.builder.build_int_compare(
+                inkwell::IntPredicate::NE,
+                flag.into_int_value(),
+                self.module.get_context().i8_type().const_int(0, false),
+                "",
+            );
+            self.builder
+                .build_conditional_branch(interrupted, exit_bb, dest_bb);
+
+            // Stop at the loop header, as if the VM was interrupted right after the BACK7
+            self.builder.position_at_end(exit_bb);
+            self.build_exit(fun_context, fun_context.ip.get() - 6, STATUS_INTERRUPTED);
+
+            self.builder.position_at_end(not_taken_bb);
+            self.build_count(fun_context.not_taken);
+            self.builder.build_unconditional_branch(new_bb);
 
             // Modifier the builder's cursor
             self.builder.position_at_end(new_bb);
         }
+        Ok(())
     }
 
-    fn spill(&self, _vm: &VM, _instr: u8) {
+    fn spill<O: ExecutionObserver>(&self, _vm: &mut VM<O>, _instr: u8) -> Result<(), VmError> {
         if let Some(fun_context) = self.fun_context.borrow_mut().as_mut() {
             let current_bb = fun_context.function.get_last_basic_block().unwrap();
             fun_context.spilled_bbs.push(current_bb);
@@ -328,5 +862,6 @@ impl<'ctx> Interpreter for JittedInterpreter<'ctx> {
             self.builder.build_unconditional_branch(basic_block);
             self.builder.position_at_end(basic_block);
         }
+        Ok(())
     }
 }
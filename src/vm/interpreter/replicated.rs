@@ -0,0 +1,156 @@
+//! Token threaded interpreter with replicated dispatch.
+//!
+//! The opcode bytes of the program are the tokens: every handler executes its
+//! instruction, then looks up the handler of the next token in [`HANDLERS`] and
+//! calls it. Each handler ends with its own copy of the dispatch code, so the
+//! branch predictor keeps a separate history for the indirect branch following
+//! every opcode, instead of a single one for the `match` of
+//! [`SimpleInterpreter`](super::simple::SimpleInterpreter).
+//!
+//! The calls are in tail position and are compiled to jumps in optimized builds.
+//! Rust does not guarantee it, so a chain of handlers returns to the dispatch
+//! loop after [`CHAIN`] instructions, which bounds the stack of unoptimized builds.
+//!
+//! Faults, and the instructions which may fault, are left to the simple
+//! interpreter, which reports them with the registers of the VM.
+
+use crate::vm::{error::VmError, observer::ExecutionObserver, ExecutionOutcome, VM};
+
+use super::threaded::{Registers, CHAIN};
+
+/// Why a chain of handlers returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Exit {
+    /// The chain is over, or the fuel is exhausted
+    Continue,
+    /// The VM halted, or a taken BACK7 saw a pending interrupt
+    Stop,
+    /// The simple interpreter has to execute the instruction at IP
+    Step,
+}
+
+type Handler = fn(&mut Registers, &[u8], u32) -> Exit;
+
+/// Handler of every token, indexed by its byte.
+static HANDLERS: [Handler; 256] = {
+    let mut handlers = [invalid as Handler; 256];
+    handlers[0x00] = halt;
+    handlers[0x01] = clra;
+    handlers[0x02] = inc3a;
+    handlers[0x03] = deca;
+    handlers[0x04] = setl;
+    handlers[0x05] = back7;
+    handlers
+};
+
+/// Dispatch code ending every handler: calls the handler of the token at IP.
+macro_rules! dispatch {
+    ($regs: expr, $code: expr, $chain: expr) => {{
+        if $chain == 0 || $regs.fuel == 0 {
+            return Exit::Continue;
+        }
+        match $code.get($regs.ip as usize) {
+            Some(token) => return HANDLERS[*token as usize]($regs, $code, $chain - 1),
+            None => return Exit::Step,
+        }
+    }};
+}
+
+fn halt(regs: &mut Registers, _code: &[u8], _chain: u32) -> Exit {
+    regs.halted = true;
+    regs.fuel -= 1;
+    Exit::Stop
+}
+
+fn clra(regs: &mut Registers, code: &[u8], chain: u32) -> Exit {
+    regs.acc = 0;
+    regs.retire();
+    dispatch!(regs, code, chain)
+}
+
+fn inc3a(regs: &mut Registers, code: &[u8], chain: u32) -> Exit {
+    match regs.overflow_mode.add(regs.acc, 3) {
+        Some(acc) => regs.acc = acc,
+        None => return Exit::Step,
+    }
+    regs.retire();
+    dispatch!(regs, code, chain)
+}
+
+fn deca(regs: &mut Registers, code: &[u8], chain: u32) -> Exit {
+    match regs.overflow_mode.add(regs.acc, -1) {
+        Some(acc) => regs.acc = acc,
+        None => return Exit::Step,
+    }
+    regs.retire();
+    dispatch!(regs, code, chain)
+}
+
+fn setl(regs: &mut Registers, code: &[u8], chain: u32) -> Exit {
+    regs.lc = regs.acc;
+    regs.retire();
+    dispatch!(regs, code, chain)
+}
+
+fn back7(regs: &mut Registers, code: &[u8], chain: u32) -> Exit {
+    let lc = match regs.overflow_mode.add(regs.lc, -1) {
+        Some(lc) => lc,
+        None => return Exit::Step,
+    };
+    if lc > 0 {
+        let target = match regs.ip.checked_sub(6) {
+            Some(target) => target,
+            None => return Exit::Step,
+        };
+        regs.lc = lc;
+        regs.ip = target;
+        regs.taken += 1;
+        regs.fuel -= 1;
+        if regs.interrupt.is_interrupted() {
+            return Exit::Stop;
+        }
+    } else {
+        regs.lc = lc;
+        regs.not_taken += 1;
+        regs.retire();
+    }
+    dispatch!(regs, code, chain)
+}
+
+fn invalid(_regs: &mut Registers, _code: &[u8], _chain: u32) -> Exit {
+    Exit::Step
+}
+
+pub struct ReplicatedInterpreter;
+
+impl ReplicatedInterpreter {
+    pub fn execute<O: ExecutionObserver>(&self, vm: &mut VM<O>) -> Result<ExecutionOutcome, VmError> {
+        super::drive(vm, |vm| Ok(self.dispatch(vm)))
+    }
+
+    /// Runs chains of handlers until HALT, a taken BACK7 seeing a pending interrupt,
+    /// or an instruction left to the simple interpreter. Returns true in the last case.
+    fn dispatch<O: ExecutionObserver>(&self, vm: &mut VM<O>) -> bool {
+        let mut regs = Registers::load(vm);
+        let code = &vm.running_program.data[..];
+
+        let step = loop {
+            if regs.fuel == 0 {
+                break false;
+            }
+            let exit = match code.get(regs.ip as usize) {
+                Some(token) => HANDLERS[*token as usize](&mut regs, code, CHAIN),
+                None => Exit::Step,
+            };
+            match exit {
+                Exit::Continue => (),
+                Exit::Stop => break false,
+                Exit::Step => break true,
+            }
+        };
+
+        regs.store(vm);
+
+        step
+    }
+}
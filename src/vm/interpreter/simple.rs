@@ -1,76 +1,167 @@
 use crate::{vm::{
+    error::VmError,
+    observer::{ExecutionObserver, Register},
     opcode::{OpCode},
-    VM,
+    ExecutionOutcome, VM,
 }, measure_time};
 
 use super::Interpreter;
 
 pub struct SimpleInterpreter;
 
+impl SimpleInterpreter {
+    /// Applies the overflow mode of the VM, trapping at the current instruction.
+    fn add<O: ExecutionObserver>(&self, vm: &VM<O>, value: i32, delta: i32) -> Result<i32, VmError> {
+        vm.overflow_mode
+            .add(value, delta)
+            .ok_or(VmError::ArithmeticOverflow {
+                ip: vm.state.ip,
+                acc: vm.state.acc,
+                lc: vm.state.lc,
+            })
+    }
+
+    /// Executes the instruction at IP, consuming one unit of fuel if the VM has a limit.
+    pub fn step<O: ExecutionObserver>(&self, vm: &mut VM<O>) -> Result<(), VmError> {
+        let ip = vm.state.ip;
+        let instr = match vm.running_program.data.get(ip as usize) {
+            Some(instr) => *instr,
+            None => {
+                return Err(VmError::IpOutOfBounds {
+                    ip,
+                    acc: vm.state.acc,
+                    lc: vm.state.lc,
+                })
+            }
+        };
+        if let Ok(opcode) = OpCode::try_from(instr) {
+            vm.observer.before_instruction(&vm.state, opcode);
+        }
+
+        match OpCode::try_from(instr) {
+            Ok(OpCode::HALT) => self.halt(vm, instr)?,
+            Ok(OpCode::CLRA) => self.clra(vm, instr)?,
+            Ok(OpCode::INC3A) => self.inc3a(vm, instr)?,
+            Ok(OpCode::DECA) => self.deca(vm, instr)?,
+            Ok(OpCode::SETL) => self.setl(vm, instr)?,
+            Ok(OpCode::BACK7) => self.back7(vm, instr)?,
+            // SPILL is only used internally by the JIT, it is not a valid user opcode
+            Ok(OpCode::SPILL) | Err(_) => {
+                return Err(VmError::InvalidOpcode {
+                    opcode: instr,
+                    ip,
+                    acc: vm.state.acc,
+                    lc: vm.state.lc,
+                })
+            }
+        }
+
+        vm.instructions += 1;
+        if let Some(fuel) = vm.fuel {
+            vm.fuel = Some(fuel - 1);
+        }
+
+        Ok(())
+    }
+}
+
 impl Interpreter for SimpleInterpreter {
 
-    fn run(&self, vm: &VM) {
+    fn run<O: ExecutionObserver>(&self, vm: &mut VM<O>) -> Result<ExecutionOutcome, VmError> {
         
+        let mut result = Ok(ExecutionOutcome::Halted);
         let elapsed_time = measure_time!({
             loop {
                 if vm.is_halt() {
                     break;
                 }
 
-                let instr = vm.running_program.data[vm.registers.ip_value() as usize];
-                // println!("pc={}, acc={}, lc={}: {:?}", vm.registers.ip_value(), vm.registers.acc_value(), vm.registers.lc_value(), OpCode::try_from(instr).unwrap());
-
-                match OpCode::try_from(instr).unwrap() {
-                    OpCode::HALT => self.halt(vm, instr),
-                    OpCode::CLRA => self.clra(vm, instr),
-                    OpCode::INC3A => self.inc3a(vm, instr),
-                    OpCode::DECA => self.deca(vm, instr),
-                    OpCode::SETL => self.setl(vm, instr),
-                    OpCode::BACK7 => self.back7(vm, instr),
-                    _ => ()
+                if let Some(interrupted) = vm.take_interrupt() {
+                    result = Ok(interrupted);
+                    break;
+                }
+
+                if vm.fuel == Some(0) {
+                    result = Ok(vm.out_of_fuel());
+                    break;
+                }
+
+                if let Err(e) = self.step(vm) {
+                    result = Err(e);
+                    break;
                 }
             }
         });
 
-        vm.running_time.replace(elapsed_time);
+        vm.running_time = elapsed_time;
 
+        result
     }
 
-    fn halt(&self, vm: &VM, _instr: u8) {
-        vm.halt.replace(true);
+    fn halt<O: ExecutionObserver>(&self, vm: &mut VM<O>, _instr: u8) -> Result<(), VmError> {
+        vm.state.halted = true;
+        vm.observer.halt(&vm.state);
+        Ok(())
     }
 
-    fn clra(&'_ self, vm: &VM, _instr: u8) {
-        vm.registers.acc.replace(0);
-        vm.registers.ip.replace(vm.registers.ip_value() + 1);
+    fn clra<O: ExecutionObserver>(&'_ self, vm: &mut VM<O>, _instr: u8) -> Result<(), VmError> {
+        vm.state.acc = 0;
+        vm.observer.register_write(vm.state.ip, Register::Acc, 0);
+        vm.state.ip += 1;
+        Ok(())
     }
 
-    fn inc3a(&'_ self, vm: &VM, _instr: u8) {
-        vm.registers.acc.replace(vm.registers.acc_value() + 3);
-        vm.registers.ip.replace(vm.registers.ip_value() + 1);
+    fn inc3a<O: ExecutionObserver>(&'_ self, vm: &mut VM<O>, _instr: u8) -> Result<(), VmError> {
+        let acc = self.add(vm, vm.state.acc, 3)?;
+        vm.state.acc = acc;
+        vm.observer.register_write(vm.state.ip, Register::Acc, acc);
+        vm.state.ip += 1;
+        Ok(())
     }
 
-    fn deca(&'_ self, vm: &VM, _instr: u8) {
-        vm.registers.acc.replace(vm.registers.acc_value() - 1);
-        vm.registers.ip.replace(vm.registers.ip_value() + 1);
+    fn deca<O: ExecutionObserver>(&'_ self, vm: &mut VM<O>, _instr: u8) -> Result<(), VmError> {
+        let acc = self.add(vm, vm.state.acc, -1)?;
+        vm.state.acc = acc;
+        vm.observer.register_write(vm.state.ip, Register::Acc, acc);
+        vm.state.ip += 1;
+        Ok(())
     }
 
-    fn setl(&'_ self, vm: &VM, _instr: u8) {
-        vm.registers.lc.replace(vm.registers.acc_value());
-        vm.registers.ip.replace(vm.registers.ip_value() + 1);
+    fn setl<O: ExecutionObserver>(&'_ self, vm: &mut VM<O>, _instr: u8) -> Result<(), VmError> {
+        vm.state.lc = vm.state.acc;
+        vm.observer.register_write(vm.state.ip, Register::Lc, vm.state.lc);
+        vm.state.ip += 1;
+        Ok(())
     }
 
-    fn back7(&'_ self, vm: &VM, _instr: u8) {
-        vm.registers.lc.replace(vm.registers.lc_value() - 1);
-        if vm.registers.lc_value() > 0 {
-            vm.registers.ip.replace(vm.registers.ip_value() - 6);
+    fn back7<O: ExecutionObserver>(&'_ self, vm: &mut VM<O>, _instr: u8) -> Result<(), VmError> {
+        let ip = vm.state.ip;
+        let lc = self.add(vm, vm.state.lc, -1)?;
+        if lc > 0 {
+            // Check the jump target before touching the registers, so that the
+            // error reports the state of the machine at the faulting BACK7.
+            let target = ip.checked_sub(6).ok_or(VmError::IpUnderflow {
+                ip,
+                acc: vm.state.acc,
+                lc: vm.state.lc,
+            })?;
+            vm.state.lc = lc;
+            vm.state.ip = target;
+            vm.back_edges_taken += 1;
+            vm.observer.register_write(ip, Register::Lc, lc);
+            vm.observer.back_edge_taken(ip, target);
         }
         else {
-            vm.registers.ip.replace(vm.registers.ip_value() + 1);
+            vm.state.lc = lc;
+            vm.state.ip = ip + 1;
+            vm.back_edges_not_taken += 1;
+            vm.observer.register_write(ip, Register::Lc, lc);
+            vm.observer.back_edge_not_taken(ip);
         }
+        Ok(())
     }
 
-    fn spill(&self, _vm: &VM, _instr: u8) {
+    fn spill<O: ExecutionObserver>(&self, _vm: &mut VM<O>, _instr: u8) -> Result<(), VmError> {
         unreachable!()
     }
 }
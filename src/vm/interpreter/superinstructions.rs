@@ -0,0 +1,289 @@
+//! Interpreter running a program rewritten into superinstructions.
+//!
+//! The decoded program is rewritten so that a single dispatch executes several
+//! instructions:
+//!
+//! - `ADD k` for a run of INC3A and DECA,
+//! - `SET k` for a CLRA followed by a run of INC3A and DECA,
+//! - `SETL ADD k` for a SETL followed by such a run, typically the header of a loop.
+//!
+//! A superinstruction never spans the target of a BACK7, which are remapped to the
+//! index of the superinstruction starting at them. The run following a SETL is
+//! usually the body of a loop, whose BACK7 targets its start: it is fused with the
+//! SETL all the same, and a separate `ADD k` is kept for the back edge. Where no
+//! superinstruction starts, when the fuel left cannot cover one, and when a run may
+//! saturate or trap, the simple interpreter executes a single instruction instead.
+
+use std::time::Duration;
+
+use crate::{
+    measure_time,
+    vm::{error::VmError, observer::ExecutionObserver, program::Program, ExecutionOutcome, OverflowMode, VM},
+};
+
+use super::decoded::Instr;
+
+/// Run of INC3A and DECA: the offset it adds to ACC, with the lowest and highest
+/// offsets reached along the run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Run {
+    delta: i64,
+    low: i64,
+    high: i64,
+}
+
+impl Run {
+    fn push(&mut self, delta: i64) {
+        self.delta += delta;
+        self.low = self.low.min(self.delta);
+        self.high = self.high.max(self.delta);
+    }
+
+    /// ACC after the run, or `None` if it saturates or traps along the way.
+    fn apply(&self, acc: i32, overflow_mode: OverflowMode) -> Option<i32> {
+        if overflow_mode == OverflowMode::Wrapping {
+            return Some(acc.wrapping_add(self.delta as i32));
+        }
+        let acc = acc as i64;
+        let fits = i32::try_from(acc + self.low).is_ok() && i32::try_from(acc + self.high).is_ok();
+        fits.then(|| (acc + self.delta) as i32)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Halt,
+    Setl,
+    Add(Run),
+    Set(i32),
+    /// SETL then the run, followed in the code by the `Add` of the run alone if
+    /// `back_edge` is set, which only a BACK7 jumps to
+    SetlAdd { run: Run, back_edge: bool },
+    /// BACK7 jumping to the superinstruction at index `target`
+    Back7 { target: u32 },
+    /// Instruction which faults or may fault, the simple interpreter executes it
+    Fault,
+}
+
+/// Superinstruction standing for the `len` instructions starting at `ip`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Op {
+    kind: Kind,
+    ip: u32,
+    len: u32,
+}
+
+pub struct SuperProgram {
+    code: Box<[Op]>,
+    // Index of the superinstruction starting at each IP, if any
+    entries: Box<[Option<u32>]>,
+    rewrite_time: Duration,
+}
+
+impl SuperProgram {
+    pub fn rewrite(program: &Program) -> Self {
+        let (code, entries);
+        let rewrite_time = measure_time!({
+            (code, entries) = Self::superinstructions(program);
+        });
+
+        Self {
+            code: code.into_boxed_slice(),
+            entries: entries.into_boxed_slice(),
+            rewrite_time,
+        }
+    }
+
+    fn superinstructions(program: &Program) -> (Vec<Op>, Vec<Option<u32>>) {
+        let mut instrs: Vec<Instr> = program
+            .data
+            .iter()
+            .enumerate()
+            .map(|(index, instr)| Instr::decode(index as u32, *instr))
+            .collect();
+        instrs.push(Instr::End);
+
+        // Superinstructions may start at a BACK7 target, not span it
+        let mut targets = vec![false; instrs.len()];
+        for instr in &instrs {
+            if let Instr::Back7 { target } = instr {
+                targets[*target as usize] = true;
+            }
+        }
+
+        // Extends `run` with the INC3A and DECA starting at `ip`, returning the end of the run
+        let extend = |ip: usize, run: &mut Run| {
+            let mut end = ip;
+            while end < instrs.len() && (end == ip || !targets[end]) {
+                match instrs[end] {
+                    Instr::Inc3a => run.push(3),
+                    Instr::Deca => run.push(-1),
+                    _ => break,
+                }
+                end += 1;
+            }
+            end
+        };
+
+        let mut code = vec![];
+        let mut entries = vec![None; instrs.len()];
+        let mut ip = 0;
+        while ip < instrs.len() {
+            let (kind, end) = match instrs[ip] {
+                Instr::Halt => (Kind::Halt, ip + 1),
+                Instr::Inc3a | Instr::Deca => {
+                    let mut add = Run::default();
+                    let end = extend(ip, &mut add);
+                    (Kind::Add(add), end)
+                }
+                Instr::Clra if ip + 1 < instrs.len() && !targets[ip + 1] => {
+                    let mut add = Run::default();
+                    let end = extend(ip + 1, &mut add);
+                    // The run cannot overflow from 0 unless it is billions of instructions long
+                    match add.apply(0, OverflowMode::Trap) {
+                        Some(value) => (Kind::Set(value), end),
+                        None => (Kind::Set(0), ip + 1),
+                    }
+                }
+                Instr::Clra => (Kind::Set(0), ip + 1),
+                Instr::Setl if ip + 1 < instrs.len() => {
+                    let mut add = Run::default();
+                    match extend(ip + 1, &mut add) {
+                        end if end > ip + 1 => {
+                            let back_edge = targets[ip + 1];
+                            (Kind::SetlAdd { run: add, back_edge }, end)
+                        }
+                        _ => (Kind::Setl, ip + 1),
+                    }
+                }
+                Instr::Setl => (Kind::Setl, ip + 1),
+                // Remapped once every superinstruction has an index
+                Instr::Back7 { target } => (Kind::Back7 { target }, ip + 1),
+                Instr::Back7Underflow | Instr::Invalid(_) | Instr::End => (Kind::Fault, ip + 1),
+            };
+
+            entries[ip] = Some(code.len() as u32);
+            code.push(Op {
+                kind,
+                ip: ip as u32,
+                len: (end - ip) as u32,
+            });
+            if let Kind::SetlAdd { run, back_edge: true } = kind {
+                entries[ip + 1] = Some(code.len() as u32);
+                code.push(Op {
+                    kind: Kind::Add(run),
+                    ip: ip as u32 + 1,
+                    len: (end - ip - 1) as u32,
+                });
+            }
+            ip = end;
+        }
+
+        for op in code.iter_mut() {
+            if let Kind::Back7 { target } = &mut op.kind {
+                *target = entries[*target as usize].expect("BACK7 targets start a superinstruction");
+            }
+        }
+
+        (code, entries)
+    }
+
+    /// Time spent decoding and rewriting the program.
+    pub fn rewrite_time(&self) -> Duration {
+        self.rewrite_time
+    }
+
+    pub fn execute<O: ExecutionObserver>(&self, vm: &mut VM<O>) -> Result<ExecutionOutcome, VmError> {
+        super::drive(vm, |vm| self.dispatch(vm))
+    }
+
+    /// Runs until HALT, a fault, a taken BACK7 seeing a pending interrupt, or an IP
+    /// at which no superinstruction can run. Returns true in the last case.
+    fn dispatch<O: ExecutionObserver>(&self, vm: &mut VM<O>) -> Result<bool, VmError> {
+        let overflow_mode = vm.overflow_mode;
+        let mut ip = vm.state.ip;
+        let mut acc = vm.state.acc;
+        let mut lc = vm.state.lc;
+        let mut fuel = vm.fuel.unwrap_or(u64::MAX);
+        let mut taken = 0;
+        let mut not_taken = 0;
+        let mut dispatches = 0;
+
+        let mut pc = match self.entries.get(ip as usize) {
+            Some(Some(pc)) => *pc as usize,
+            _ => return Ok(true),
+        };
+
+        let result = loop {
+            let op = self.code[pc];
+            if fuel < op.len as u64 {
+                break Ok(true);
+            }
+
+            match op.kind {
+                Kind::Halt => {
+                    vm.state.halted = true;
+                    fuel -= 1;
+                    dispatches += 1;
+                    break Ok(false);
+                }
+                Kind::Setl => lc = acc,
+                Kind::Add(run) => match run.apply(acc, overflow_mode) {
+                    Some(value) => acc = value,
+                    None => break Ok(true),
+                },
+                Kind::Set(value) => acc = value,
+                Kind::SetlAdd { run, back_edge } => match run.apply(acc, overflow_mode) {
+                    Some(value) => {
+                        lc = acc;
+                        acc = value;
+                        // Skip the ADD kept for the back edge
+                        if back_edge {
+                            pc += 1;
+                        }
+                    }
+                    None => break Ok(true),
+                },
+                Kind::Back7 { target } => match overflow_mode.add(lc, -1) {
+                    Some(value) if value > 0 => {
+                        lc = value;
+                        pc = target as usize;
+                        ip = self.code[pc].ip;
+                        taken += 1;
+                        fuel -= 1;
+                        dispatches += 1;
+                        if vm.interrupt.is_interrupted() {
+                            break Ok(false);
+                        }
+                        continue;
+                    }
+                    Some(value) => {
+                        lc = value;
+                        not_taken += 1;
+                    }
+                    None => break Err(VmError::ArithmeticOverflow { ip, acc, lc }),
+                },
+                Kind::Fault => break Ok(true),
+            }
+
+            pc += 1;
+            ip += op.len;
+            fuel -= op.len as u64;
+            dispatches += 1;
+        };
+
+        let retired = vm.fuel.unwrap_or(u64::MAX) - fuel;
+        vm.state.ip = ip;
+        vm.state.acc = acc;
+        vm.state.lc = lc;
+        vm.back_edges_taken += taken;
+        vm.back_edges_not_taken += not_taken;
+        vm.dispatches_saved += retired - dispatches;
+        vm.instructions += retired;
+        if vm.fuel.is_some() {
+            vm.fuel = Some(fuel);
+        }
+
+        result
+    }
+}
@@ -0,0 +1,597 @@
+//! Template JIT emitting x86-64 machine code without a compiler backend.
+//!
+//! Every opcode has a fixed template of machine code, copied for each instruction
+//! of the program into executable memory. ACC and LC stay in registers for the
+//! whole call, and BACK7 becomes a `dec`/`jg` pair jumping to the loop header.
+//!
+//! The program is split into the blocks of the LLVM backend, and the generated
+//! function follows the same convention as [`RunFunc`]: it takes the same
+//! arguments, checks the fuel at the start of every block, polls the interrupt
+//! flag after every taken BACK7 and returns the same status codes. The same
+//! loop runs it, so both JITs report their results identically.
+
+use std::{
+    io, ptr,
+    time::{Duration, Instant},
+};
+
+use crate::vm::{error::VmError, observer::ExecutionObserver, opcode::OpCode, ExecutionOutcome, OverflowMode, VM};
+
+use super::jitted::{
+    self, RunFunc, STATUS_HALT, STATUS_INTERRUPTED, STATUS_NO_ENTRY, STATUS_OUT_OF_FUEL, STATUS_OVERFLOW,
+};
+
+/// General purpose registers, numbered as in their encoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+enum Reg {
+    Rax = 0,
+    Rcx = 1,
+    Rdx = 2,
+    Rbx = 3,
+    Rsp = 4,
+    Rsi = 6,
+    Rdi = 7,
+    R8 = 8,
+    R9 = 9,
+    R10 = 10,
+    R11 = 11,
+    R12 = 12,
+    R13 = 13,
+    R14 = 14,
+    R15 = 15,
+}
+
+impl Reg {
+    fn low(self) -> u8 {
+        self as u8 & 7
+    }
+}
+
+// Arguments of the function, in the order of the System V calling convention. The
+// pointer to the interrupt flag is passed on the stack and loaded into R10.
+const ACC_PTR: Reg = Reg::Rdi;
+const LC_PTR: Reg = Reg::Rsi;
+const IP_PTR: Reg = Reg::Rdx;
+const FUEL_PTR: Reg = Reg::Rcx;
+const TAKEN_PTR: Reg = Reg::R8;
+const NOT_TAKEN_PTR: Reg = Reg::R9;
+const INTERRUPT_PTR: Reg = Reg::R10;
+
+// Registers and counters of the VM, in callee saved registers
+const ACC: Reg = Reg::R12;
+const LC: Reg = Reg::R13;
+const FUEL: Reg = Reg::R14;
+const TAKEN: Reg = Reg::R15;
+const NOT_TAKEN: Reg = Reg::Rbx;
+const SAVED: [Reg; 5] = [Reg::Rbx, Reg::R12, Reg::R13, Reg::R14, Reg::R15];
+
+/// Condition codes of `jcc`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+enum Cond {
+    Overflow = 0x0,
+    NoOverflow = 0x1,
+    Below = 0x2,
+    AboveOrEqual = 0x3,
+    Zero = 0x4,
+    NotZero = 0x5,
+    Greater = 0xf,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Label(usize);
+
+/// Exit of the function out of the straight-line code, emitted after it.
+struct Stub {
+    label: Label,
+    ip: u32,
+    status: u32,
+    // Addition to undo on a trap, and the fuel of the instructions of the block
+    // which did not retire
+    undo: Option<(Reg, i8, u32)>,
+}
+
+/// Emits the handful of instructions the templates are made of.
+#[derive(Default)]
+struct Assembler {
+    code: Vec<u8>,
+    // Offset of every label, once bound
+    labels: Vec<Option<usize>>,
+    // Offsets of the 32 bits displacements to patch, with the label they point to
+    fixups: Vec<(usize, Label)>,
+}
+
+impl Assembler {
+    fn label(&mut self) -> Label {
+        self.labels.push(None);
+        Label(self.labels.len() - 1)
+    }
+
+    fn bind(&mut self, label: Label) {
+        self.labels[label.0] = Some(self.code.len());
+    }
+
+    fn offset(&self, label: Label) -> usize {
+        self.labels[label.0].expect("labels are bound before the code is finished")
+    }
+
+    fn emit(&mut self, bytes: &[u8]) {
+        self.code.extend_from_slice(bytes);
+    }
+
+    fn rel32(&mut self, label: Label) {
+        self.fixups.push((self.code.len(), label));
+        self.emit(&[0; 4]);
+    }
+
+    /// REX prefix extending `reg` and `rm`, omitted when it would be empty.
+    fn rex(&mut self, wide: bool, reg: u8, rm: u8) {
+        let rex = 0x40 | (wide as u8) << 3 | (reg >> 3) << 2 | rm >> 3;
+        if rex != 0x40 {
+            self.emit(&[rex]);
+        }
+    }
+
+    fn modrm(&mut self, mode: u8, reg: u8, rm: u8) {
+        self.emit(&[mode << 6 | (reg & 7) << 3 | rm & 7]);
+    }
+
+    fn push(&mut self, reg: Reg) {
+        self.rex(false, 0, reg as u8);
+        self.emit(&[0x50 + reg.low()]);
+    }
+
+    fn pop(&mut self, reg: Reg) {
+        self.rex(false, 0, reg as u8);
+        self.emit(&[0x58 + reg.low()]);
+    }
+
+    /// `mov dst, [base]`, none of the bases used needs a SIB byte.
+    fn load(&mut self, dst: Reg, base: Reg, wide: bool) {
+        self.rex(wide, dst as u8, base as u8);
+        self.emit(&[0x8b]);
+        self.modrm(0b00, dst as u8, base as u8);
+    }
+
+    /// `mov [base], src`
+    fn store(&mut self, base: Reg, src: Reg, wide: bool) {
+        self.rex(wide, src as u8, base as u8);
+        self.emit(&[0x89]);
+        self.modrm(0b00, src as u8, base as u8);
+    }
+
+    /// `mov dst, [rsp + disp]`
+    fn load_stack(&mut self, dst: Reg, disp: u8) {
+        self.rex(true, dst as u8, Reg::Rsp as u8);
+        self.emit(&[0x8b]);
+        self.modrm(0b01, dst as u8, Reg::Rsp as u8);
+        self.emit(&[0x24, disp]);
+    }
+
+    /// `mov dword [base], imm`
+    fn store_imm(&mut self, base: Reg, imm: u32) {
+        self.rex(false, 0, base as u8);
+        self.emit(&[0xc7]);
+        self.modrm(0b00, 0, base as u8);
+        self.emit(&imm.to_le_bytes());
+    }
+
+    /// `mov dst, src` on 32 bits
+    fn mov(&mut self, dst: Reg, src: Reg) {
+        self.rex(false, src as u8, dst as u8);
+        self.emit(&[0x89]);
+        self.modrm(0b11, src as u8, dst as u8);
+    }
+
+    /// `mov dst, imm` on 32 bits
+    fn mov_imm(&mut self, dst: Reg, imm: u32) {
+        self.rex(false, 0, dst as u8);
+        self.emit(&[0xb8 + dst.low()]);
+        self.emit(&imm.to_le_bytes());
+    }
+
+    /// `xor dst, dst` on 32 bits
+    fn clear(&mut self, dst: Reg) {
+        self.rex(false, dst as u8, dst as u8);
+        self.emit(&[0x31]);
+        self.modrm(0b11, dst as u8, dst as u8);
+    }
+
+    /// `add dst, imm` with a sign extended 8 bits immediate
+    fn add_imm8(&mut self, dst: Reg, imm: i8, wide: bool) {
+        self.rex(wide, 0, dst as u8);
+        self.emit(&[0x83]);
+        self.modrm(0b11, 0, dst as u8);
+        self.emit(&[imm as u8]);
+    }
+
+    /// `add`, `sub` or `cmp` of `dst` with a sign extended 32 bits immediate
+    fn alu_imm32(&mut self, extension: u8, dst: Reg, imm: u32, wide: bool) {
+        self.rex(wide, 0, dst as u8);
+        self.emit(&[0x81]);
+        self.modrm(0b11, extension, dst as u8);
+        self.emit(&imm.to_le_bytes());
+    }
+
+    fn add_imm32(&mut self, dst: Reg, imm: u32, wide: bool) {
+        self.alu_imm32(0, dst, imm, wide);
+    }
+
+    fn sub_imm32(&mut self, dst: Reg, imm: u32, wide: bool) {
+        self.alu_imm32(5, dst, imm, wide);
+    }
+
+    fn cmp_imm32(&mut self, dst: Reg, imm: u32, wide: bool) {
+        self.alu_imm32(7, dst, imm, wide);
+    }
+
+    fn inc(&mut self, dst: Reg, wide: bool) {
+        self.rex(wide, 0, dst as u8);
+        self.emit(&[0xff]);
+        self.modrm(0b11, 0, dst as u8);
+    }
+
+    fn dec(&mut self, dst: Reg, wide: bool) {
+        self.rex(wide, 0, dst as u8);
+        self.emit(&[0xff]);
+        self.modrm(0b11, 1, dst as u8);
+    }
+
+    /// `cmp byte [base], 0`
+    fn cmp_byte_zero(&mut self, base: Reg) {
+        self.rex(false, 0, base as u8);
+        self.emit(&[0x80]);
+        self.modrm(0b00, 7, base as u8);
+        self.emit(&[0]);
+    }
+
+    /// `lea dst, [rip + label]`
+    fn lea(&mut self, dst: Reg, label: Label) {
+        self.rex(true, dst as u8, 0);
+        self.emit(&[0x8d]);
+        self.modrm(0b00, dst as u8, 0b101);
+        self.rel32(label);
+    }
+
+    /// `movsxd dst, dword [base + index * 4]`, `index` being one of the first 8 registers
+    fn load_entry(&mut self, dst: Reg, base: Reg, index: Reg) {
+        self.rex(true, dst as u8, base as u8);
+        self.emit(&[0x63]);
+        self.modrm(0b00, dst as u8, 0b100);
+        self.emit(&[0b10 << 6 | index.low() << 3 | base.low()]);
+    }
+
+    /// `test reg, reg` on 64 bits
+    fn test(&mut self, reg: Reg) {
+        self.rex(true, reg as u8, reg as u8);
+        self.emit(&[0x85]);
+        self.modrm(0b11, reg as u8, reg as u8);
+    }
+
+    /// `add dst, src` on 64 bits
+    fn add(&mut self, dst: Reg, src: Reg) {
+        self.rex(true, src as u8, dst as u8);
+        self.emit(&[0x01]);
+        self.modrm(0b11, src as u8, dst as u8);
+    }
+
+    fn jmp_reg(&mut self, reg: Reg) {
+        self.rex(false, 0, reg as u8);
+        self.emit(&[0xff]);
+        self.modrm(0b11, 4, reg as u8);
+    }
+
+    fn jmp(&mut self, label: Label) {
+        self.emit(&[0xe9]);
+        self.rel32(label);
+    }
+
+    fn jcc(&mut self, cond: Cond, label: Label) {
+        self.emit(&[0x0f, 0x80 | cond as u8]);
+        self.rel32(label);
+    }
+
+    fn ret(&mut self) {
+        self.emit(&[0xc3]);
+    }
+
+    /// Patches the displacement of every jump, once all the labels are bound.
+    fn finish(mut self) -> Vec<u8> {
+        for (at, label) in std::mem::take(&mut self.fixups) {
+            let rel = self.offset(label) as i64 - (at + 4) as i64;
+            self.code[at..at + 4].copy_from_slice(&(rel as i32).to_le_bytes());
+        }
+        self.code
+    }
+}
+
+/// Generates the function running `data`, which `check_program` accepted.
+fn emit(data: &[u8], blocks: &[Vec<u8>], overflow_mode: OverflowMode) -> Result<Vec<u8>, String> {
+    let mut asm = Assembler::default();
+    let mut stubs = vec![];
+    let (halt, back7, spill): (u8, u8, u8) = (OpCode::HALT.into(), OpCode::BACK7.into(), OpCode::SPILL.into());
+
+    // Loop headers, entered from their BACK7 through the code counting it
+    let mut back_edges = vec![None; data.len()];
+    for (index, instr) in data.iter().enumerate() {
+        if *instr == back7 {
+            back_edges[index - 6] = Some(asm.label());
+        }
+    }
+
+    let (store_exit, restore, no_entry, table) = (asm.label(), asm.label(), asm.label(), asm.label());
+
+    // The seventh argument is above the return address
+    asm.load_stack(INTERRUPT_PTR, 8);
+    for reg in SAVED {
+        asm.push(reg);
+    }
+    asm.load(ACC, ACC_PTR, false);
+    asm.load(LC, LC_PTR, false);
+    asm.load(FUEL, FUEL_PTR, true);
+    asm.load(TAKEN, TAKEN_PTR, true);
+    asm.load(NOT_TAKEN, NOT_TAKEN_PTR, true);
+
+    // Jump to the block starting at IP, through a table of offsets from the table
+    asm.load(Reg::Rax, IP_PTR, false);
+    asm.cmp_imm32(Reg::Rax, data.len() as u32, false);
+    asm.jcc(Cond::AboveOrEqual, no_entry);
+    asm.lea(Reg::R11, table);
+    asm.load_entry(Reg::Rax, Reg::R11, Reg::Rax);
+    asm.test(Reg::Rax);
+    asm.jcc(Cond::Zero, no_entry);
+    asm.add(Reg::Rax, Reg::R11);
+    asm.jmp_reg(Reg::Rax);
+
+    let mut entries = vec![None; data.len()];
+    let mut falls_through = false;
+    let mut ip = 0u32;
+    for block in blocks {
+        // SPILL instructions are not part of the program and have no IP
+        let instructions: Vec<_> = block.iter().copied().filter(|instr| *instr != spill).collect();
+        if instructions.is_empty() {
+            continue;
+        }
+        let start = ip;
+        let cost = match instructions.iter().position(|instr| *instr == halt) {
+            Some(halt) => halt + 1,
+            None => instructions.len(),
+        };
+        let cost = u32::try_from(cost)
+            .ok()
+            .filter(|cost| *cost <= i32::MAX as u32)
+            .ok_or_else(|| format!("block at {} is too long", start))?;
+
+        let entry = asm.label();
+        if let Some(back_edge) = back_edges[start as usize] {
+            if falls_through {
+                asm.jmp(entry);
+            }
+            // Stop at the loop header, as if the VM was interrupted right after the BACK7
+            let interrupted = asm.label();
+            stubs.push(Stub { label: interrupted, ip: start, status: STATUS_INTERRUPTED, undo: None });
+            asm.bind(back_edge);
+            asm.inc(TAKEN, true);
+            asm.cmp_byte_zero(INTERRUPT_PTR);
+            asm.jcc(Cond::NotZero, interrupted);
+        }
+        asm.bind(entry);
+        entries[start as usize] = Some(entry);
+
+        let out_of_fuel = asm.label();
+        stubs.push(Stub { label: out_of_fuel, ip: start, status: STATUS_OUT_OF_FUEL, undo: None });
+        asm.cmp_imm32(FUEL, cost, true);
+        asm.jcc(Cond::Below, out_of_fuel);
+        asm.sub_imm32(FUEL, cost, true);
+
+        falls_through = true;
+        for instr in &instructions[..cost as usize] {
+            // Fuel of the instructions of the block after this one
+            let unretired = cost - (ip - start);
+            match OpCode::try_from(*instr).expect("the program was checked") {
+                OpCode::HALT => {
+                    asm.store_imm(IP_PTR, ip);
+                    asm.mov_imm(Reg::Rax, STATUS_HALT);
+                    asm.jmp(store_exit);
+                    falls_through = false;
+                }
+                OpCode::CLRA => asm.clear(ACC),
+                OpCode::INC3A => emit_add(&mut asm, &mut stubs, overflow_mode, ip, 3, unretired),
+                OpCode::DECA => emit_add(&mut asm, &mut stubs, overflow_mode, ip, -1, unretired),
+                OpCode::SETL => asm.mov(LC, ACC),
+                OpCode::BACK7 => {
+                    let back_edge = back_edges[ip as usize - 6].expect("BACK7 targets have a label");
+                    asm.dec(LC, false);
+                    match overflow_mode {
+                        // LC wraps around to i32::MAX, which is positive
+                        OverflowMode::Wrapping => {
+                            asm.jcc(Cond::Greater, back_edge);
+                            asm.jcc(Cond::Overflow, back_edge);
+                        }
+                        OverflowMode::Saturating => {
+                            let next = asm.label();
+                            asm.jcc(Cond::Greater, back_edge);
+                            asm.jcc(Cond::NoOverflow, next);
+                            asm.mov_imm(LC, i32::MIN as u32);
+                            asm.bind(next);
+                        }
+                        OverflowMode::Trap => {
+                            let trap = asm.label();
+                            stubs.push(Stub {
+                                label: trap,
+                                ip,
+                                status: STATUS_OVERFLOW,
+                                undo: Some((LC, -1, unretired)),
+                            });
+                            asm.jcc(Cond::Overflow, trap);
+                            asm.jcc(Cond::Greater, back_edge);
+                        }
+                    }
+                    asm.inc(NOT_TAKEN, true);
+                }
+                OpCode::SPILL => unreachable!("SPILL instructions were filtered out"),
+            }
+            ip += 1;
+        }
+        // Instructions after a HALT are never executed
+        ip = start + instructions.len() as u32;
+    }
+
+    for stub in stubs {
+        asm.bind(stub.label);
+        if let Some((reg, delta, unretired)) = stub.undo {
+            asm.add_imm8(reg, -delta, false);
+            asm.add_imm32(FUEL, unretired, true);
+        }
+        asm.store_imm(IP_PTR, stub.ip);
+        asm.mov_imm(Reg::Rax, stub.status);
+        asm.jmp(store_exit);
+    }
+
+    // Writes the registers back through the arguments, the status being in EAX
+    asm.bind(store_exit);
+    asm.store(ACC_PTR, ACC, false);
+    asm.store(LC_PTR, LC, false);
+    asm.store(FUEL_PTR, FUEL, true);
+    asm.store(TAKEN_PTR, TAKEN, true);
+    asm.store(NOT_TAKEN_PTR, NOT_TAKEN, true);
+    asm.bind(restore);
+    for reg in SAVED.iter().rev() {
+        asm.pop(*reg);
+    }
+    asm.ret();
+
+    // The registers are left untouched if there is no block at IP
+    asm.bind(no_entry);
+    asm.mov_imm(Reg::Rax, STATUS_NO_ENTRY);
+    asm.jmp(restore);
+
+    while asm.code.len() % 4 != 0 {
+        asm.emit(&[0xcc]);
+    }
+    asm.bind(table);
+    let table_offset = asm.code.len() as i64;
+    for entry in entries {
+        // Code never starts at the table, 0 marks the IPs without a block
+        let offset = entry.map_or(0, |entry| asm.offset(entry) as i64 - table_offset);
+        asm.emit(&(offset as i32).to_le_bytes());
+    }
+
+    Ok(asm.finish())
+}
+
+/// Emits `ACC + delta` following the overflow mode, so that the code behaves
+/// exactly as the simple interpreter.
+fn emit_add(asm: &mut Assembler, stubs: &mut Vec<Stub>, overflow_mode: OverflowMode, ip: u32, delta: i8, unretired: u32) {
+    asm.add_imm8(ACC, delta, false);
+    match overflow_mode {
+        OverflowMode::Wrapping => (),
+        OverflowMode::Saturating => {
+            let next = asm.label();
+            let limit = if delta >= 0 { i32::MAX } else { i32::MIN };
+            asm.jcc(Cond::NoOverflow, next);
+            asm.mov_imm(ACC, limit as u32);
+            asm.bind(next);
+        }
+        OverflowMode::Trap => {
+            let trap = asm.label();
+            stubs.push(Stub { label: trap, ip, status: STATUS_OVERFLOW, undo: Some((ACC, delta, unretired)) });
+            asm.jcc(Cond::Overflow, trap);
+        }
+    }
+}
+
+/// Pages mapped executable, holding the code of a function.
+struct ExecutableMemory {
+    ptr: *mut libc::c_void,
+    len: usize,
+}
+
+impl ExecutableMemory {
+    /// Copies `code` into fresh pages, then makes them executable and read only.
+    fn new(code: &[u8]) -> io::Result<Self> {
+        let len = code.len();
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANON,
+                -1,
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        let memory = Self { ptr, len };
+
+        unsafe {
+            ptr::copy_nonoverlapping(code.as_ptr(), ptr as *mut u8, len);
+            if libc::mprotect(ptr, len, libc::PROT_READ | libc::PROT_EXEC) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+
+        Ok(memory)
+    }
+}
+
+impl Drop for ExecutableMemory {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.ptr, self.len);
+        }
+    }
+}
+
+/// Native code of a program, generated from the templates.
+pub struct TemplateCode {
+    // The function points into the memory, which is unmapped when the code is dropped
+    function: RunFunc,
+    _memory: ExecutableMemory,
+    compile_time: Duration,
+}
+
+// The pages are never written once executable, and the function only accesses
+// the registers it is given: the code can be called from any thread.
+unsafe impl Send for TemplateCode {}
+unsafe impl Sync for TemplateCode {}
+
+impl TemplateCode {
+    /// Generates the code of the program of `vm`, following its overflow mode.
+    pub fn compile<O: ExecutionObserver>(vm: &mut VM<O>) -> Result<Self, VmError> {
+        jitted::check_program(vm)?;
+
+        let start = Instant::now();
+        let blocks = vm.running_program.build_basic_blocks();
+        let memory = emit(&vm.running_program.data, &blocks, vm.overflow_mode)
+            .and_then(|code| ExecutableMemory::new(&code).map_err(|e| e.to_string()))
+            .map_err(|message| VmError::JitCompilation {
+                message,
+                ip: vm.state.ip,
+                acc: vm.state.acc,
+                lc: vm.state.lc,
+            })?;
+        let compile_time = start.elapsed();
+
+        // SAFETY: the code follows the System V convention of `RunFunc`
+        let function = unsafe { std::mem::transmute::<*mut libc::c_void, RunFunc>(memory.ptr) };
+
+        Ok(Self {
+            function,
+            _memory: memory,
+            compile_time,
+        })
+    }
+
+    pub fn execute<O: ExecutionObserver>(&self, vm: &mut VM<O>) -> Result<ExecutionOutcome, VmError> {
+        jitted::execute(self.function, vm)
+    }
+
+    /// Time spent generating the code and mapping it executable.
+    pub fn compile_time(&self) -> Duration {
+        self.compile_time
+    }
+}
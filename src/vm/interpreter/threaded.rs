@@ -0,0 +1,254 @@
+//! Interpreter dispatching through a handler pointer stored in every instruction.
+//!
+//! The program is decoded as for [`DecodedProgram`](super::decoded::DecodedProgram),
+//! then every instruction is paired with the function executing it. There is no
+//! `match` on the opcode while running: a handler executes its instruction, then
+//! calls the handler stored in the instruction at IP itself, so the indirect
+//! branches are spread across the handlers instead of a single call site.
+//!
+//! Optimized builds turn these calls in tail position into jumps. Rust does not
+//! guarantee it, and each call would otherwise grow the stack, so handlers return
+//! to the dispatch loop every [`CHAIN`] instructions.
+
+use std::time::Duration;
+
+use crate::{
+    measure_time,
+    vm::{
+        error::VmError, observer::ExecutionObserver, program::Program, ExecutionOutcome, Interrupt, OverflowMode, VM,
+    },
+};
+
+use super::decoded::Instr;
+
+/// Instructions executed by a chain of handlers before returning to the dispatch loop.
+pub(super) const CHAIN: u32 = 1024;
+
+/// Registers and counters of a run, owned by the dispatch loop until it stops.
+pub(super) struct Registers {
+    pub(super) ip: u32,
+    pub(super) acc: i32,
+    pub(super) lc: i32,
+    pub(super) halted: bool,
+    pub(super) fuel: u64,
+    pub(super) taken: u64,
+    pub(super) not_taken: u64,
+    pub(super) overflow_mode: OverflowMode,
+    pub(super) interrupt: Interrupt,
+}
+
+impl Registers {
+    /// Loads the registers of `vm`, with the fuel left to the run.
+    pub(super) fn load<O: ExecutionObserver>(vm: &VM<O>) -> Self {
+        Self {
+            ip: vm.state.ip,
+            acc: vm.state.acc,
+            lc: vm.state.lc,
+            halted: false,
+            fuel: vm.fuel.unwrap_or(u64::MAX),
+            taken: 0,
+            not_taken: 0,
+            overflow_mode: vm.overflow_mode,
+            interrupt: vm.interrupt.clone(),
+        }
+    }
+
+    /// Writes the registers back to `vm`, and counts the instructions retired.
+    pub(super) fn store<O: ExecutionObserver>(&self, vm: &mut VM<O>) {
+        vm.state.ip = self.ip;
+        vm.state.acc = self.acc;
+        vm.state.lc = self.lc;
+        vm.state.halted |= self.halted;
+        vm.back_edges_taken += self.taken;
+        vm.back_edges_not_taken += self.not_taken;
+        vm.instructions += vm.fuel.unwrap_or(u64::MAX) - self.fuel;
+        if vm.fuel.is_some() {
+            vm.fuel = Some(self.fuel);
+        }
+    }
+
+    /// Applies the overflow mode, trapping at the current instruction.
+    pub(super) fn add(&self, value: i32, delta: i32) -> Result<i32, VmError> {
+        self.overflow_mode.add(value, delta).ok_or(VmError::ArithmeticOverflow {
+            ip: self.ip,
+            acc: self.acc,
+            lc: self.lc,
+        })
+    }
+
+    pub(super) fn retire(&mut self) {
+        self.ip += 1;
+        self.fuel -= 1;
+    }
+}
+
+/// Executes an instruction, then dispatches at most `chain` more. Returns false if
+/// the dispatch loop has to stop, true once the chain is over or the fuel exhausted.
+type Handler = fn(&mut Registers, &[Op], u32, u32) -> Result<bool, VmError>;
+
+/// Instruction with its handler. The operand is the target of a BACK7, or the
+/// byte of an invalid opcode.
+#[derive(Clone, Copy)]
+struct Op {
+    handler: Handler,
+    operand: u32,
+}
+
+impl Op {
+    fn thread(instr: Instr) -> Self {
+        let (handler, operand): (Handler, u32) = match instr {
+            Instr::Halt => (halt, 0),
+            Instr::Clra => (clra, 0),
+            Instr::Inc3a => (inc3a, 0),
+            Instr::Deca => (deca, 0),
+            Instr::Setl => (setl, 0),
+            Instr::Back7 { target } => (back7, target),
+            Instr::Back7Underflow => (back7_underflow, 0),
+            Instr::Invalid(opcode) => (invalid, opcode as u32),
+            Instr::End => (end, 0),
+        };
+        Self { handler, operand }
+    }
+}
+
+/// Dispatch code ending every handler: calls the handler of the instruction at IP.
+macro_rules! dispatch {
+    ($regs: expr, $code: expr, $chain: expr) => {{
+        if $chain == 0 || $regs.fuel == 0 {
+            return Ok(true);
+        }
+        let op = $code[$regs.ip as usize];
+        return (op.handler)($regs, $code, op.operand, $chain - 1);
+    }};
+}
+
+fn halt(regs: &mut Registers, _code: &[Op], _operand: u32, _chain: u32) -> Result<bool, VmError> {
+    regs.halted = true;
+    regs.fuel -= 1;
+    Ok(false)
+}
+
+fn clra(regs: &mut Registers, code: &[Op], _operand: u32, chain: u32) -> Result<bool, VmError> {
+    regs.acc = 0;
+    regs.retire();
+    dispatch!(regs, code, chain)
+}
+
+fn inc3a(regs: &mut Registers, code: &[Op], _operand: u32, chain: u32) -> Result<bool, VmError> {
+    regs.acc = regs.add(regs.acc, 3)?;
+    regs.retire();
+    dispatch!(regs, code, chain)
+}
+
+fn deca(regs: &mut Registers, code: &[Op], _operand: u32, chain: u32) -> Result<bool, VmError> {
+    regs.acc = regs.add(regs.acc, -1)?;
+    regs.retire();
+    dispatch!(regs, code, chain)
+}
+
+fn setl(regs: &mut Registers, code: &[Op], _operand: u32, chain: u32) -> Result<bool, VmError> {
+    regs.lc = regs.acc;
+    regs.retire();
+    dispatch!(regs, code, chain)
+}
+
+fn back7(regs: &mut Registers, code: &[Op], target: u32, chain: u32) -> Result<bool, VmError> {
+    regs.lc = regs.add(regs.lc, -1)?;
+    if regs.lc > 0 {
+        regs.ip = target;
+        regs.taken += 1;
+        regs.fuel -= 1;
+        if regs.interrupt.is_interrupted() {
+            return Ok(false);
+        }
+        dispatch!(regs, code, chain)
+    }
+    regs.not_taken += 1;
+    regs.retire();
+    dispatch!(regs, code, chain)
+}
+
+fn back7_underflow(regs: &mut Registers, code: &[Op], _operand: u32, chain: u32) -> Result<bool, VmError> {
+    let lc = regs.add(regs.lc, -1)?;
+    if lc > 0 {
+        return Err(VmError::IpUnderflow {
+            ip: regs.ip,
+            acc: regs.acc,
+            lc: regs.lc,
+        });
+    }
+    regs.lc = lc;
+    regs.not_taken += 1;
+    regs.retire();
+    dispatch!(regs, code, chain)
+}
+
+fn invalid(regs: &mut Registers, _code: &[Op], opcode: u32, _chain: u32) -> Result<bool, VmError> {
+    Err(VmError::InvalidOpcode {
+        opcode: opcode as u8,
+        ip: regs.ip,
+        acc: regs.acc,
+        lc: regs.lc,
+    })
+}
+
+fn end(regs: &mut Registers, _code: &[Op], _operand: u32, _chain: u32) -> Result<bool, VmError> {
+    Err(VmError::IpOutOfBounds {
+        ip: regs.ip,
+        acc: regs.acc,
+        lc: regs.lc,
+    })
+}
+
+pub struct ThreadedProgram {
+    code: Box<[Op]>,
+    decode_time: Duration,
+}
+
+impl ThreadedProgram {
+    pub fn decode(program: &Program) -> Self {
+        let mut code = Vec::with_capacity(program.data.len() + 1);
+        let decode_time = measure_time!({
+            for (index, instr) in program.data.iter().enumerate() {
+                code.push(Op::thread(Instr::decode(index as u32, *instr)));
+            }
+            code.push(Op::thread(Instr::End));
+        });
+
+        Self {
+            code: code.into_boxed_slice(),
+            decode_time,
+        }
+    }
+
+    pub fn decode_time(&self) -> Duration {
+        self.decode_time
+    }
+
+    pub fn execute<O: ExecutionObserver>(&self, vm: &mut VM<O>) -> Result<ExecutionOutcome, VmError> {
+        super::drive(vm, |vm| self.dispatch(vm).map(|()| false))
+    }
+
+    /// Runs chains of handlers until HALT, a fault, the end of the fuel, or a taken
+    /// BACK7 seeing a pending interrupt, then writes the registers and counters back to `vm`.
+    fn dispatch<O: ExecutionObserver>(&self, vm: &mut VM<O>) -> Result<(), VmError> {
+        let mut regs = Registers::load(vm);
+
+        let result = loop {
+            if regs.fuel == 0 {
+                break Ok(());
+            }
+
+            let op = self.code[regs.ip as usize];
+            match (op.handler)(&mut regs, &self.code, op.operand, CHAIN) {
+                Ok(true) => (),
+                Ok(false) => break Ok(()),
+                Err(e) => break Err(e),
+            }
+        };
+
+        regs.store(vm);
+
+        result
+    }
+}
@@ -1,96 +1,470 @@
+pub mod assembler;
+pub mod batch;
+pub mod builder;
+pub mod compiled;
+pub mod disassembler;
+pub mod error;
+pub mod observer;
 pub mod opcode;
 pub mod program;
+pub mod report;
+pub mod snapshot;
 pub mod utils;
+pub mod verifier;
 
 mod interpreter;
 
-use std::{cell::Cell, borrow::Borrow, time::Duration};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, RecvTimeoutError},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
 
 use self::interpreter::Interpreter;
-use inkwell::{context::Context, OptimizationLevel};
+use builder::VmBuilder;
+use compiled::CompiledProgram;
+use error::{VerifyError, VmError};
+use observer::{ExecutionObserver, NoopObserver};
 use program::Program;
+use report::ExecutionReport;
 
-#[derive(Debug, Clone, serde::Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
 pub enum RunningMode {
     Simple,
     NoOptJitted,
-    OptJitted
+    OptJitted,
+    /// Interprets the program decoded once before its first run.
+    Decoded,
+    /// Interprets the decoded program, calling the handler stored in every instruction.
+    Threaded,
+    /// Runs the program translated into closures, one per block.
+    Closures,
+    /// Interprets the decoded program rewritten into superinstructions.
+    Superinstructions,
+    /// Interprets the opcodes of the program, every handler dispatching to the next one.
+    Replicated,
+    /// Runs x86-64 code emitted from a fixed template per opcode, without LLVM.
+    TemplateJit,
+    /// JIT compiled with Cranelift, without optimizations. Needs the `cranelift` feature.
+    NoOptCranelift,
+    /// JIT compiled with Cranelift, optimized for speed. Needs the `cranelift` feature.
+    OptCranelift,
 }
 
-#[derive(Debug, PartialEq)]
-struct Registers {
-    ip: Cell<u32>,  // Instruction Pointer
-    acc: Cell<i32>, // Accumulator
-    lc: Cell<i32>,  // Loop Counter
+/// Behaviour of INC3A, DECA and the decrement of BACK7 when the result does not
+/// fit in 32 bits. Every [`RunningMode`] implements the same semantics.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize)]
+pub enum OverflowMode {
+    /// Two's complement wrap around.
+    #[default]
+    Wrapping,
+    /// Clamp the result to `i32::MIN` / `i32::MAX`.
+    Saturating,
+    /// Stop with [`VmError::ArithmeticOverflow`] at the faulting instruction.
+    Trap,
 }
 
+impl OverflowMode {
+    /// Adds `delta` to `value`, returning `None` if the operation traps.
+    pub fn add(self, value: i32, delta: i32) -> Option<i32> {
+        match self {
+            OverflowMode::Wrapping => Some(value.wrapping_add(delta)),
+            OverflowMode::Saturating => Some(value.saturating_add(delta)),
+            OverflowMode::Trap => value.checked_add(delta),
+        }
+    }
+}
+
+/// How a call to [`VM::run`] terminated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExecutionOutcome {
+    /// The program reached a HALT instruction.
+    Halted,
+    /// The fuel given to [`VM::run_with_fuel`] was exhausted before reaching a HALT.
+    /// The registers hold the state after the last retired instruction, and the VM
+    /// can be resumed by running it again.
+    OutOfFuel { ip: u32, acc: i32, lc: i32 },
+    /// The [`Interrupt`] of the VM was triggered. The registers hold the state after the
+    /// last retired instruction, and the VM can be resumed by running it again.
+    Interrupted { ip: u32, acc: i32, lc: i32 },
+}
+
+/// Result of [`VM::step`] and [`VM::run_until`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepResult {
+    /// The VM can execute the instruction at IP.
+    Continue,
+    /// The VM is halted, stepping it again has no effect.
+    Halted,
+}
+
+/// Handle stopping a running [`VM`], which can be sent to another thread.
+///
+/// The simple interpreter polls it before every instruction, while the other modes
+/// check it each time a BACK7 jumps back. The run observing the request returns
+/// [`ExecutionOutcome::Interrupted`] and clears it.
+#[derive(Debug, Clone, Default)]
+pub struct Interrupt(Arc<AtomicBool>);
+
+impl Interrupt {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Requests the VM to stop at the next check.
+    pub fn interrupt(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_interrupted(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Withdraws a request which has not been observed yet.
+    pub fn clear(&self) {
+        self.0.store(false, Ordering::Relaxed);
+    }
+
+    /// Requests the VM to stop unless a request is already pending. Returns true if
+    /// this call made the request.
+    fn interrupt_if_clear(&self) -> bool {
+        self.0
+            .compare_exchange(false, true, Ordering::Relaxed, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    // Flag read by the JIT compiled code with relaxed (monotonic) atomic loads,
+    // `AtomicBool` has the layout of a `u8`
+    fn as_ptr(&self) -> *const u8 {
+        Arc::as_ptr(&self.0) as *const u8
+    }
+}
+
+/// Registers and halt flag of a [`VM`], everything a run modifies.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct VmState {
+    /// Instruction Pointer
+    pub ip: u32,
+    /// Accumulator
+    pub acc: i32,
+    /// Loop Counter
+    pub lc: i32,
+    pub halted: bool,
+}
+
+/// Virtual machine running a [`Program`], reporting to an [`ExecutionObserver`].
 #[derive(Debug)]
-pub struct VM {
-    registers: Registers,
-    running_program: Program,
+pub struct VM<O: ExecutionObserver = NoopObserver> {
+    state: VmState,
+    // State the VM boots with, restored by `VM::reset`
+    boot: VmState,
+    // Shared with the `CompiledProgram` the VM was booted from, if any
+    running_program: Arc<Program>,
     mode: RunningMode,
-    halt: Cell<bool>,
-    pub running_time: Cell<Duration>,
+    overflow_mode: OverflowMode,
+    // Instructions left to the current run, `None` if it is unbounded
+    fuel: Option<u64>,
+    // Instructions retired and BACK7 executed since the VM booted
+    instructions: u64,
+    back_edges_taken: u64,
+    back_edges_not_taken: u64,
+    // Instructions retired by superinstructions without a dispatch of their own
+    dispatches_saved: u64,
+    // Limits applied by `VM::run`
+    fuel_limit: Option<u64>,
+    timeout: Option<Duration>,
+    interrupt: Interrupt,
+    observer: O,
+    // Code of the program, decoded or compiled by the first run needing it
+    compiled: Option<CompiledProgram>,
+    // Report of the last run
+    report: ExecutionReport,
+    pub running_time: Duration,
 }
 
-impl PartialEq for VM {
+impl<O: ExecutionObserver> PartialEq for VM<O> {
     fn eq(&self, other: &Self) -> bool {
-        self.registers == other.registers
+        self.state == other.state
     }
 }
 
-impl std::fmt::Display for VM {
+impl<O: ExecutionObserver> std::fmt::Display for VM<O> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
             "VM(ip: {}, acc: {}, lc: {}, running_time: {:.2?})",
-            self.registers.ip.get(), self.registers.acc.get(), self.registers.lc.get(), self.running_time.get()
+            self.state.ip, self.state.acc, self.state.lc, self.running_time
         )
     }
 }
 
-impl Registers {
-    pub fn ip_value(&self) -> u32 { self.ip.get() }
-    pub fn acc_value(&self) -> i32 { self.acc.get() }
-    pub fn lc_value(&self) -> i32 { self.lc.get() }
-}
-
 impl VM {
     pub fn new(mode: RunningMode, running_program: Program) -> Self {
-        Self {
-            registers: Registers {
-                ip: Cell::new(0),
-                acc: Cell::new(running_program.initial_acc),
-                lc: Cell::new(running_program.initial_lc),
-            },
-            halt: Cell::new(false),
-            running_time: Cell::new(Duration::new(0, 0)),
-            running_program,
-            mode,
+        VmBuilder::new(running_program).mode(mode).build()
+    }
+
+    /// Returns a [`VmBuilder`] to override the boot registers of the program, the
+    /// overflow mode or the limits of the runs.
+    pub fn builder(running_program: Program) -> VmBuilder {
+        VmBuilder::new(running_program)
+    }
+
+    /// Same as [`VM::new`], but rejects programs for which the [`verifier`] reports errors.
+    pub fn new_verified(mode: RunningMode, running_program: Program) -> Result<Self, VerifyError> {
+        VmBuilder::new(running_program).mode(mode).build_verified()
+    }
+}
+
+impl<O: ExecutionObserver> VM<O> {
+    /// Reports the execution to `observer`, replacing the current one.
+    pub fn with_observer<P: ExecutionObserver>(self, observer: P) -> VM<P> {
+        VM {
+            state: self.state,
+            boot: self.boot,
+            running_program: self.running_program,
+            mode: self.mode,
+            overflow_mode: self.overflow_mode,
+            fuel: self.fuel,
+            instructions: self.instructions,
+            back_edges_taken: self.back_edges_taken,
+            back_edges_not_taken: self.back_edges_not_taken,
+            dispatches_saved: self.dispatches_saved,
+            fuel_limit: self.fuel_limit,
+            timeout: self.timeout,
+            interrupt: self.interrupt,
+            observer,
+            compiled: self.compiled,
+            report: self.report,
+            running_time: self.running_time,
         }
     }
 
-    fn is_halt(&self) -> bool {
-        self.halt.borrow().get()
+    pub fn observer(&self) -> &O {
+        &self.observer
+    }
+
+    pub fn observer_mut(&mut self) -> &mut O {
+        &mut self.observer
     }
 
-    pub fn run(&self) {
+    pub fn into_observer(self) -> O {
+        self.observer
+    }
+
+    /// Sets how arithmetic overflows are handled, [`OverflowMode::Wrapping`] by default.
+    pub fn with_overflow_mode(mut self, overflow_mode: OverflowMode) -> Self {
+        self.overflow_mode = overflow_mode;
+        self
+    }
+
+    /// Uses `interrupt` instead of a handle of its own, e.g. to stop several VMs at once.
+    pub fn with_interrupt(mut self, interrupt: Interrupt) -> Self {
+        self.interrupt = interrupt;
+        self
+    }
+
+    /// Returns a handle to interrupt the VM from another thread.
+    pub fn interrupt_handle(&self) -> Interrupt {
+        self.interrupt.clone()
+    }
+
+    pub fn is_halt(&self) -> bool {
+        self.state.halted
+    }
+
+    /// Copy of the registers and halt flag.
+    pub fn state(&self) -> VmState {
+        self.state
+    }
+
+    /// Replaces the registers and halt flag, e.g. to resume from a state saved earlier.
+    pub fn set_state(&mut self, state: VmState) {
+        self.state = state;
+    }
+
+    /// Instruction Pointer, the offset of the next instruction to execute.
+    pub fn ip(&self) -> u32 {
+        self.state.ip
+    }
+
+    /// Accumulator (register A).
+    pub fn acc(&self) -> i32 {
+        self.state.acc
+    }
+
+    /// Loop Counter (register L).
+    pub fn lc(&self) -> i32 {
+        self.state.lc
+    }
+
+    /// Number of instructions retired since the VM booted or was reset.
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    pub fn program(&self) -> &Program {
+        &self.running_program
+    }
+
+    /// Report of the last call to one of the `run` methods.
+    pub fn report(&self) -> ExecutionReport {
+        self.report
+    }
+
+    /// Restores the boot registers, so that the program can be run again.
+    pub fn reset(&mut self) {
+        self.state = self.boot;
+        self.instructions = 0;
+        self.back_edges_taken = 0;
+        self.back_edges_not_taken = 0;
+        self.dispatches_saved = 0;
+        self.report = ExecutionReport::default();
+        self.running_time = Duration::new(0, 0);
+    }
+
+    fn out_of_fuel(&self) -> ExecutionOutcome {
+        ExecutionOutcome::OutOfFuel {
+            ip: self.state.ip,
+            acc: self.state.acc,
+            lc: self.state.lc,
+        }
+    }
+
+    // Clears the pending interrupt request, if any
+    fn take_interrupt(&self) -> Option<ExecutionOutcome> {
+        if !self.interrupt.is_interrupted() {
+            return None;
+        }
+        self.interrupt.clear();
+
+        Some(ExecutionOutcome::Interrupted {
+            ip: self.state.ip,
+            acc: self.state.acc,
+            lc: self.state.lc,
+        })
+    }
+
+    /// Runs the program until it halts, or until one of the limits given to the
+    /// [`VmBuilder`] is reached. A VM which is already halted returns immediately.
+    pub fn run(&mut self) -> Result<ExecutionOutcome, VmError> {
+        self.run_limited(self.fuel_limit, self.timeout)
+    }
+
+    /// Runs the program, stopping with [`ExecutionOutcome::OutOfFuel`] once `fuel`
+    /// instructions have been retired. Running the VM again resumes the execution.
+    pub fn run_with_fuel(&mut self, fuel: u64) -> Result<ExecutionOutcome, VmError> {
+        self.run_limited(Some(fuel), self.timeout)
+    }
+
+    /// Runs the program, stopping with [`ExecutionOutcome::Interrupted`] if it does not
+    /// halt within `timeout`. Running the VM again resumes the execution.
+    pub fn run_with_timeout(&mut self, timeout: Duration) -> Result<ExecutionOutcome, VmError> {
+        self.run_limited(self.fuel_limit, Some(timeout))
+    }
+
+    fn run_limited(&mut self, fuel: Option<u64>, timeout: Option<Duration>) -> Result<ExecutionOutcome, VmError> {
+        let instructions = self.instructions;
+        let back_edges_taken = self.back_edges_taken;
+        let back_edges_not_taken = self.back_edges_not_taken;
+        let dispatches_saved = self.dispatches_saved;
+        // The compilation times are filled by `execute` when the run uses compiled code
+        self.report = ExecutionReport::default();
+        self.running_time = Duration::new(0, 0);
+
+        self.fuel = fuel;
+        let result = match timeout {
+            Some(timeout) => self.execute_with_timeout(timeout),
+            None => self.execute(),
+        };
+        self.fuel = None;
+
+        self.report = ExecutionReport {
+            ip: self.state.ip,
+            acc: self.state.acc,
+            lc: self.state.lc,
+            halted: self.state.halted,
+            instructions: self.instructions - instructions,
+            back_edges_taken: self.back_edges_taken - back_edges_taken,
+            back_edges_not_taken: self.back_edges_not_taken - back_edges_not_taken,
+            dispatches_saved: self.dispatches_saved - dispatches_saved,
+            execute_time: self.running_time,
+            ..self.report
+        };
+        result
+    }
+
+    /// Executes a single instruction with the interpreter, whatever the running mode.
+    pub fn step(&mut self) -> Result<StepResult, VmError> {
+        if !self.is_halt() {
+            interpreter::simple::SimpleInterpreter.step(self)?;
+        }
+
+        Ok(if self.is_halt() {
+            StepResult::Halted
+        } else {
+            StepResult::Continue
+        })
+    }
+
+    /// Steps the VM until `predicate` holds before executing the instruction at IP,
+    /// returning [`StepResult::Continue`], or until the program halts.
+    pub fn run_until<F: FnMut(&Self) -> bool>(&mut self, mut predicate: F) -> Result<StepResult, VmError> {
+        while !self.is_halt() {
+            if predicate(self) {
+                return Ok(StepResult::Continue);
+            }
+            self.step()?;
+        }
+
+        Ok(StepResult::Halted)
+    }
+
+    fn execute_with_timeout(&mut self, timeout: Duration) -> Result<ExecutionOutcome, VmError> {
+        let (done, timer) = mpsc::channel::<()>();
+        let interrupt = self.interrupt.clone();
+        let timer = thread::spawn(move || {
+            let expired = timer.recv_timeout(timeout) == Err(RecvTimeoutError::Timeout);
+            expired && interrupt.interrupt_if_clear()
+        });
+
+        let result = self.execute();
+        drop(done);
+
+        // The deadline may pass after the run ended, the request must not stop the next
+        // one. A request made by another thread is left pending.
+        let requested = timer.join().unwrap_or(false);
+        if requested && !matches!(result, Ok(ExecutionOutcome::Interrupted { .. })) {
+            self.interrupt.clear();
+        }
+
+        result
+    }
+
+    fn execute(&mut self) -> Result<ExecutionOutcome, VmError> {
 
         match self.mode {
             RunningMode::Simple => {
-                interpreter::simple::SimpleInterpreter {}.run(self);
+                interpreter::simple::SimpleInterpreter {}.run(self)
             },
-            RunningMode::NoOptJitted | RunningMode::OptJitted => {
-
-                let opt_level = match self.mode {
-                    RunningMode::NoOptJitted => OptimizationLevel::None,
-                    RunningMode::OptJitted => OptimizationLevel::Default,
-                    _ => unreachable!()
+            // Decoded and compiled code do not report to the observer
+            _ if O::ENABLED => {
+                interpreter::simple::SimpleInterpreter {}.run(self)
+            },
+            _ => {
+                // Compile once per VM, unless the code was compiled ahead of time
+                let compiled = match self.compiled.take() {
+                    Some(compiled) if compiled.matches(self) => compiled,
+                    _ => CompiledProgram::compile_for(self)?,
                 };
-
-                let ctx = Context::create();
-                let jitted = interpreter::jitted::JittedInterpreter::new(ctx.borrow(), opt_level);
-                jitted.run(self);
+                self.report.compile_time = compiled.compile_time();
+                self.report.verify_time = compiled.verify_time();
+                let result = compiled.execute(self);
+                self.compiled = Some(compiled);
+                result
             }
         }
     }
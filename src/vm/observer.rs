@@ -0,0 +1,71 @@
+//! Hook point to watch a [`VM`](super::VM) while it executes.
+//!
+//! The VM is generic over its observer, so the calls to [`NoopObserver`] are
+//! inlined away and cost nothing. Decoded and JIT compiled code do not report
+//! individual instructions: a VM with an [enabled](ExecutionObserver::ENABLED)
+//! observer executes every [`RunningMode`](super::RunningMode) with the simple
+//! interpreter.
+
+use super::{opcode::OpCode, VmState};
+
+/// Register written by an instruction. IP is written by every instruction and
+/// is not reported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Register {
+    Acc,
+    Lc,
+}
+
+/// Callbacks invoked by the interpreter. Every method does nothing by default.
+pub trait ExecutionObserver {
+    /// False if the observer ignores every callback, which lets the VM use JIT
+    /// compiled code.
+    const ENABLED: bool = true;
+
+    /// Called with the state of the VM before executing `opcode` at `state.ip`.
+    fn before_instruction(&mut self, _state: &VmState, _opcode: OpCode) {}
+
+    /// The BACK7 at `ip` jumps back to `target`.
+    fn back_edge_taken(&mut self, _ip: u32, _target: u32) {}
+
+    /// The BACK7 at `ip` falls through, its loop is over.
+    fn back_edge_not_taken(&mut self, _ip: u32) {}
+
+    /// `register` is set to `value` by the instruction at `ip`.
+    fn register_write(&mut self, _ip: u32, _register: Register, _value: i32) {}
+
+    /// The HALT at `state.ip` stopped the VM.
+    fn halt(&mut self, _state: &VmState) {}
+}
+
+/// Observer of a [`VM`](super::VM) nobody watches.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NoopObserver;
+
+impl ExecutionObserver for NoopObserver {
+    const ENABLED: bool = false;
+}
+
+impl<T: ExecutionObserver> ExecutionObserver for &mut T {
+    const ENABLED: bool = T::ENABLED;
+
+    fn before_instruction(&mut self, state: &VmState, opcode: OpCode) {
+        (**self).before_instruction(state, opcode)
+    }
+
+    fn back_edge_taken(&mut self, ip: u32, target: u32) {
+        (**self).back_edge_taken(ip, target)
+    }
+
+    fn back_edge_not_taken(&mut self, ip: u32) {
+        (**self).back_edge_not_taken(ip)
+    }
+
+    fn register_write(&mut self, ip: u32, register: Register, value: i32) {
+        (**self).register_write(ip, register, value)
+    }
+
+    fn halt(&mut self, state: &VmState) {
+        (**self).halt(state)
+    }
+}
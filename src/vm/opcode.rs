@@ -1,4 +1,4 @@
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum OpCode {
     HALT = 0x00,
     CLRA = 0x01,
@@ -20,6 +20,24 @@ impl TryFrom<u8> for OpCode {
     }
 }
 
+impl std::str::FromStr for OpCode {
+    type Err = &'static str;
+
+    /// Parses a mnemonic, ignoring its case.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().as_str() {
+            "HALT" => Ok(OpCode::HALT),
+            "CLRA" => Ok(OpCode::CLRA),
+            "INC3A" => Ok(OpCode::INC3A),
+            "DECA" => Ok(OpCode::DECA),
+            "SETL" => Ok(OpCode::SETL),
+            "BACK7" => Ok(OpCode::BACK7),
+            "SPILL" => Ok(OpCode::SPILL),
+            _ => Err("invalid OpCode mnemonic"),
+        }
+    }
+}
+
 impl Into<u8> for OpCode {
     fn into(self) -> u8 {
         unsafe {
@@ -1,13 +1,18 @@
-use std::{path::PathBuf, collections::VecDeque};
+use std::{collections::VecDeque, io::Read, path::Path};
 
 use byteorder::{LittleEndian, ReadBytesExt};
 
-use super::opcode::OpCode;
+use super::{disassembler::Listing, error::ProgramLoadError, opcode::OpCode};
+
+pub mod container;
+
+/// Size of the legacy header holding the initial values of ACC and LC.
+const HEADER_SIZE: usize = 8;
 
 type Instruction = u8;
 type BasicBlock = Vec<Instruction>;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct Program {
     pub data: Vec<Instruction>,
     pub initial_acc: i32,
@@ -15,32 +20,44 @@ pub struct Program {
     pub filename: Option<String>,
 }
 
+/// Optional information stored alongside a program as sections of the
+/// [`container`] format.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProgramInfo {
+    pub name: Option<String>,
+    pub metadata: Vec<(String, String)>,
+    pub expected: Option<ExpectedRegisters>,
+    pub source_map: Vec<SourceLocation>,
+}
+
+/// Values the registers are expected to hold once the program halts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExpectedRegisters {
+    pub acc: i32,
+    pub lc: i32,
+}
+
+/// Position in the source file of the instruction at `offset`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SourceLocation {
+    pub offset: u32,
+    pub line: u32,
+    pub column: u32,
+}
+
 impl std::fmt::Display for Program {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let no_file = "<no-file>".to_string();
-        write!(
+        writeln!(
             f,
-            "Program(filename: `{}`, ACC: {}, LC: {}, Size: {}, Code: [",
+            "; Program(filename: `{}`, ACC: {}, LC: {}, Size: {})",
             self.filename.as_ref().unwrap_or(&no_file),
             self.initial_acc,
             self.initial_lc,
             self.data.len()
-        )
-        .unwrap();
-
-        // Print actual code
-        if self.data.len() <= 128 {
-            write!(f, "[\n").unwrap();
-            for (index, instr) in self.data.iter().enumerate() {
-                write!(f, "\t{}: {}\n", index + 1, OpCode::try_from(*instr).unwrap()).unwrap();
-            }
-            write!(f, "])").unwrap();
-        }
-        else {
-            write!(f, "...])").unwrap();
-        }
+        )?;
 
-        Ok(())
+        write!(f, "{}", Listing(self))
     }
 }
 
@@ -54,34 +71,31 @@ impl Program {
         }
     }
 
-    pub fn read_from_file(path: PathBuf) -> Self {
-        let filename = path.to_str().unwrap().to_string();
-
-        match std::fs::read(path) {
-            Ok(file_content) => {
-                // The first 4 bytes are the initial value of ACCUMULATOR register
-                let mut r_a = &file_content[0..4];
-                let initial_acc = r_a.read_i32::<LittleEndian>().unwrap();
-                // The second 4 bytes are the initial value of LOOP_COUNTER register
-                let mut r_lc = &file_content[4..8];
-                let initial_lc = r_lc.read_i32::<LittleEndian>().unwrap();
-
-                let data = file_content[8..].to_vec();
-                Program {
-                    data,
-                    initial_acc,
-                    initial_lc,
-                    filename: Some(filename),
-                }
-            }
-            Err(e) => {
-                eprintln!(
-                    "[error] :: an error occurred when reading the program: {}",
-                    e
-                );
-                std::process::exit(-1);
-            }
-        }
+    /// Decodes a program, either from a [`container`] or from the legacy headerless format.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ProgramLoadError> {
+        LoadOptions::new().load_bytes(bytes)
+    }
+
+    /// Decodes a program with the [`ProgramInfo`] of its container, empty for legacy files.
+    pub fn from_bytes_with_info(bytes: &[u8]) -> Result<(Self, ProgramInfo), ProgramLoadError> {
+        LoadOptions::new().load_with_info(bytes)
+    }
+
+    /// Reads the whole `reader` and decodes the program it contains.
+    pub fn from_reader<R: Read>(reader: R) -> Result<Self, ProgramLoadError> {
+        LoadOptions::new().load_reader(reader)
+    }
+
+    /// Loads the program stored at `path`, remembering its file name.
+    pub fn read_from_file<P: AsRef<Path>>(path: P) -> Result<Self, ProgramLoadError> {
+        LoadOptions::new().load_file(path)
+    }
+
+    /// Identifies the code of the program, the boot registers excluded (64-bit FNV-1a).
+    pub fn fingerprint(&self) -> u64 {
+        self.data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+            (hash ^ *byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+        })
     }
 
     pub fn build_basic_blocks(&self) -> Vec<BasicBlock> {
@@ -127,3 +141,89 @@ impl Program {
     }
 
 }
+
+/// Options controlling how a [`Program`] is loaded.
+#[derive(Debug, Clone, Default)]
+pub struct LoadOptions {
+    validate_opcodes: bool,
+}
+
+impl LoadOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Rejects programs containing bytes that are not valid user opcodes.
+    pub fn validate_opcodes(mut self, validate: bool) -> Self {
+        self.validate_opcodes = validate;
+        self
+    }
+
+    pub fn load_bytes(&self, bytes: &[u8]) -> Result<Program, ProgramLoadError> {
+        self.load_with_info(bytes).map(|(program, _)| program)
+    }
+
+    /// Loads a program with the [`ProgramInfo`] of its container, empty for legacy files.
+    pub fn load_with_info(&self, bytes: &[u8]) -> Result<(Program, ProgramInfo), ProgramLoadError> {
+        // Legacy files start with the initial ACC, which never matches the magic
+        // number for the values produced by `src/scenario/gen.c` (0 to 7).
+        if container::is_container(bytes) {
+            return container::decode(bytes, self);
+        }
+
+        if bytes.len() < HEADER_SIZE {
+            return Err(ProgramLoadError::TruncatedHeader { len: bytes.len() });
+        }
+
+        // The first 4 bytes are the initial value of ACCUMULATOR register
+        let mut r_a = &bytes[0..4];
+        let initial_acc = r_a.read_i32::<LittleEndian>()?;
+        // The second 4 bytes are the initial value of LOOP_COUNTER register
+        let mut r_lc = &bytes[4..8];
+        let initial_lc = r_lc.read_i32::<LittleEndian>()?;
+
+        let data = bytes[HEADER_SIZE..].to_vec();
+
+        if self.validate_opcodes {
+            check_opcodes(&data, HEADER_SIZE)?;
+        }
+
+        Ok((Program::new(data, initial_acc, initial_lc), ProgramInfo::default()))
+    }
+
+    pub fn load_reader<R: Read>(&self, mut reader: R) -> Result<Program, ProgramLoadError> {
+        let mut bytes = vec![];
+        reader.read_to_end(&mut bytes)?;
+        self.load_bytes(&bytes)
+    }
+
+    pub fn load_file<P: AsRef<Path>>(&self, path: P) -> Result<Program, ProgramLoadError> {
+        let path = path.as_ref();
+        let filename = path
+            .to_str()
+            .ok_or_else(|| ProgramLoadError::NonUtf8Path(path.to_path_buf()))?
+            .to_string();
+
+        let file = std::fs::File::open(path)?;
+        let mut program = self.load_reader(std::io::BufReader::new(file))?;
+        program.filename = Some(filename);
+
+        Ok(program)
+    }
+}
+
+/// Checks that `data` only contains user opcodes, reporting offsets relative to `base`.
+fn check_opcodes(data: &[u8], base: usize) -> Result<(), ProgramLoadError> {
+    for (index, instr) in data.iter().enumerate() {
+        match OpCode::try_from(*instr) {
+            Ok(OpCode::SPILL) | Err(_) => {
+                return Err(ProgramLoadError::InvalidOpcode {
+                    opcode: *instr,
+                    offset: base + index,
+                })
+            }
+            _ => (),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,285 @@
+//! Versioned binary container for programs.
+//!
+//! All the integers are little-endian. A container is made of a fixed header,
+//! the code of the program and a list of optional sections:
+//!
+//! ```text
+//! offset  size  field
+//!      0     4  magic number (`VTVM`)
+//!      4     2  format version
+//!      6     2  flags
+//!      8     4  CRC-32 of every byte following this field
+//!     12     4  initial value of ACC
+//!     16     4  initial value of LC
+//!     20     4  code length (n)
+//!     24     4  number of sections
+//!     28     n  code
+//!   28+n     -  sections, each one as `kind: u8, length: u32, payload`
+//! ```
+//!
+//! Unknown section kinds are skipped, so that older readers can still load the
+//! code of newer containers.
+
+use std::io::{Cursor, Read, Write};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use super::{check_opcodes, ExpectedRegisters, LoadOptions, Program, ProgramInfo, SourceLocation};
+use crate::vm::error::ProgramLoadError;
+
+pub const MAGIC: [u8; 4] = *b"VTVM";
+pub const VERSION: u16 = 1;
+
+/// The code only contained valid opcodes when it was written.
+pub const FLAG_VALIDATED: u16 = 0x0001;
+const KNOWN_FLAGS: u16 = FLAG_VALIDATED;
+
+const HEADER_SIZE: usize = 28;
+const CHECKSUM_END: usize = 12;
+
+const SECTION_NAME: u8 = 1;
+const SECTION_METADATA: u8 = 2;
+const SECTION_EXPECTED: u8 = 3;
+const SECTION_SOURCE_MAP: u8 = 4;
+
+/// Returns true if `bytes` starts with the container magic number.
+pub fn is_container(bytes: &[u8]) -> bool {
+    bytes.starts_with(&MAGIC)
+}
+
+pub(super) fn decode(bytes: &[u8], options: &LoadOptions) -> Result<(Program, ProgramInfo), ProgramLoadError> {
+    if bytes.len() < HEADER_SIZE {
+        return Err(ProgramLoadError::TruncatedHeader { len: bytes.len() });
+    }
+
+    let mut header = Cursor::new(&bytes[MAGIC.len()..HEADER_SIZE]);
+    let version = header.read_u16::<LittleEndian>()?;
+    if version != VERSION {
+        return Err(ProgramLoadError::UnsupportedVersion { version });
+    }
+
+    let flags = header.read_u16::<LittleEndian>()?;
+    if flags & !KNOWN_FLAGS != 0 {
+        return Err(ProgramLoadError::UnsupportedFlags { flags });
+    }
+
+    let expected = header.read_u32::<LittleEndian>()?;
+    let found = crc32(&bytes[CHECKSUM_END..]);
+    if expected != found {
+        return Err(ProgramLoadError::ChecksumMismatch { expected, found });
+    }
+
+    let initial_acc = header.read_i32::<LittleEndian>()?;
+    let initial_lc = header.read_i32::<LittleEndian>()?;
+    let code_len = header.read_u32::<LittleEndian>()? as usize;
+    let section_count = header.read_u32::<LittleEndian>()?;
+
+    let code_end = HEADER_SIZE
+        .checked_add(code_len)
+        .filter(|end| *end <= bytes.len())
+        .ok_or(ProgramLoadError::TruncatedSection { offset: HEADER_SIZE })?;
+    let data = bytes[HEADER_SIZE..code_end].to_vec();
+
+    if options.validate_opcodes || flags & FLAG_VALIDATED != 0 {
+        check_opcodes(&data, HEADER_SIZE)?;
+    }
+
+    let mut info = ProgramInfo::default();
+    let mut offset = code_end;
+    for _ in 0..section_count {
+        let (kind, payload) = read_section(bytes, offset)?;
+        decode_section(kind, payload, &mut info)
+            .map_err(|_| ProgramLoadError::InvalidSection { kind, offset })?;
+        offset += 5 + payload.len();
+    }
+
+    Ok((Program::new(data, initial_acc, initial_lc), info))
+}
+
+fn read_section(bytes: &[u8], offset: usize) -> Result<(u8, &[u8]), ProgramLoadError> {
+    let mut cursor = Cursor::new(
+        bytes
+            .get(offset..)
+            .ok_or(ProgramLoadError::TruncatedSection { offset })?,
+    );
+    let kind = cursor.read_u8().map_err(|_| ProgramLoadError::TruncatedSection { offset })?;
+    let len = cursor
+        .read_u32::<LittleEndian>()
+        .map_err(|_| ProgramLoadError::TruncatedSection { offset })? as usize;
+
+    let start = offset + 5;
+    let payload = start
+        .checked_add(len)
+        .and_then(|end| bytes.get(start..end))
+        .ok_or(ProgramLoadError::TruncatedSection { offset })?;
+
+    Ok((kind, payload))
+}
+
+fn decode_section(kind: u8, payload: &[u8], info: &mut ProgramInfo) -> std::io::Result<()> {
+    let mut cursor = Cursor::new(payload);
+    match kind {
+        SECTION_NAME => info.name = Some(read_string(&mut cursor, payload.len())?),
+        SECTION_METADATA => {
+            while (cursor.position() as usize) < payload.len() {
+                let key_len = cursor.read_u32::<LittleEndian>()? as usize;
+                let key = read_string(&mut cursor, key_len)?;
+                let value_len = cursor.read_u32::<LittleEndian>()? as usize;
+                let value = read_string(&mut cursor, value_len)?;
+                info.metadata.push((key, value));
+            }
+        }
+        SECTION_EXPECTED => {
+            let acc = cursor.read_i32::<LittleEndian>()?;
+            let lc = cursor.read_i32::<LittleEndian>()?;
+            info.expected = Some(ExpectedRegisters { acc, lc });
+        }
+        SECTION_SOURCE_MAP => {
+            while (cursor.position() as usize) < payload.len() {
+                let offset = cursor.read_u32::<LittleEndian>()?;
+                let line = cursor.read_u32::<LittleEndian>()?;
+                let column = cursor.read_u32::<LittleEndian>()?;
+                info.source_map.push(SourceLocation { offset, line, column });
+            }
+        }
+        // Sections added by newer versions of the format are ignored
+        _ => (),
+    }
+    Ok(())
+}
+
+fn read_string(cursor: &mut Cursor<&[u8]>, len: usize) -> std::io::Result<String> {
+    let mut buf = vec![0; len];
+    cursor.read_exact(&mut buf)?;
+    String::from_utf8(buf).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+}
+
+fn write_string(out: &mut Vec<u8>, value: &str) {
+    out.write_u32::<LittleEndian>(value.len() as u32).unwrap();
+    out.extend_from_slice(value.as_bytes());
+}
+
+fn encode_sections(info: &ProgramInfo) -> Vec<(u8, Vec<u8>)> {
+    let mut sections = vec![];
+
+    if let Some(name) = &info.name {
+        sections.push((SECTION_NAME, name.as_bytes().to_vec()));
+    }
+
+    if !info.metadata.is_empty() {
+        let mut payload = vec![];
+        for (key, value) in &info.metadata {
+            write_string(&mut payload, key);
+            write_string(&mut payload, value);
+        }
+        sections.push((SECTION_METADATA, payload));
+    }
+
+    if let Some(expected) = &info.expected {
+        let mut payload = vec![];
+        payload.write_i32::<LittleEndian>(expected.acc).unwrap();
+        payload.write_i32::<LittleEndian>(expected.lc).unwrap();
+        sections.push((SECTION_EXPECTED, payload));
+    }
+
+    if !info.source_map.is_empty() {
+        let mut payload = vec![];
+        for location in &info.source_map {
+            payload.write_u32::<LittleEndian>(location.offset).unwrap();
+            payload.write_u32::<LittleEndian>(location.line).unwrap();
+            payload.write_u32::<LittleEndian>(location.column).unwrap();
+        }
+        sections.push((SECTION_SOURCE_MAP, payload));
+    }
+
+    sections
+}
+
+pub(super) fn encode(program: &Program, info: &ProgramInfo) -> Vec<u8> {
+    let sections = encode_sections(info);
+
+    let flags = if check_opcodes(&program.data, 0).is_ok() {
+        FLAG_VALIDATED
+    } else {
+        0
+    };
+
+    // Writing to a `Vec` cannot fail
+    let mut out = Vec::with_capacity(HEADER_SIZE + program.data.len());
+    out.extend_from_slice(&MAGIC);
+    out.write_u16::<LittleEndian>(VERSION).unwrap();
+    out.write_u16::<LittleEndian>(flags).unwrap();
+    out.write_u32::<LittleEndian>(0).unwrap();
+    out.write_i32::<LittleEndian>(program.initial_acc).unwrap();
+    out.write_i32::<LittleEndian>(program.initial_lc).unwrap();
+    out.write_u32::<LittleEndian>(program.data.len() as u32).unwrap();
+    out.write_u32::<LittleEndian>(sections.len() as u32).unwrap();
+    out.extend_from_slice(&program.data);
+
+    for (kind, payload) in sections {
+        out.write_u8(kind).unwrap();
+        out.write_u32::<LittleEndian>(payload.len() as u32).unwrap();
+        out.extend_from_slice(&payload);
+    }
+
+    let checksum = crc32(&out[CHECKSUM_END..]);
+    out[8..CHECKSUM_END].copy_from_slice(&checksum.to_le_bytes());
+
+    out
+}
+
+impl Program {
+    /// Encodes the program in the container format, without any section.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        encode(self, &ProgramInfo::default())
+    }
+
+    /// Encodes the program in the container format, with the sections of `info`.
+    pub fn to_bytes_with_info(&self, info: &ProgramInfo) -> Vec<u8> {
+        encode(self, info)
+    }
+
+    /// Encodes the program in the legacy headerless format (ACC, LC, code).
+    pub fn to_legacy_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(super::HEADER_SIZE + self.data.len());
+        out.write_i32::<LittleEndian>(self.initial_acc).unwrap();
+        out.write_i32::<LittleEndian>(self.initial_lc).unwrap();
+        out.extend_from_slice(&self.data);
+        out
+    }
+
+    pub fn write_to<W: Write>(&self, mut writer: W) -> std::io::Result<()> {
+        writer.write_all(&self.to_bytes())
+    }
+
+    pub fn write_to_file<P: AsRef<std::path::Path>>(&self, path: P) -> std::io::Result<()> {
+        std::fs::write(path, self.to_bytes())
+    }
+}
+
+const CRC32_TABLE: [u32; 256] = crc32_table();
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// CRC-32 (IEEE 802.3), the same checksum used by zip and PNG.
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc = CRC32_TABLE[((crc ^ *byte as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
@@ -0,0 +1,69 @@
+//! Summary of a run of the [`VM`](super::VM).
+//!
+//! Every run records an [`ExecutionReport`], available from
+//! [`VM::report`](super::VM::report) until the next one. The report is flat and
+//! its durations are serialized as integer nanoseconds, so that it can be written
+//! as a CSV record.
+
+use std::time::Duration;
+
+use super::VmState;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ExecutionReport {
+    /// Registers at the end of the run.
+    pub ip: u32,
+    pub acc: i32,
+    pub lc: i32,
+    pub halted: bool,
+    /// Instructions retired by the run.
+    pub instructions: u64,
+    /// BACK7 executed by the run which jumped back.
+    pub back_edges_taken: u64,
+    /// BACK7 executed by the run which fell through.
+    pub back_edges_not_taken: u64,
+    /// Instructions retired by the run without a dispatch of their own, compared to
+    /// the simple interpreter. Only superinstructions save dispatches.
+    pub dispatches_saved: u64,
+    /// Time spent translating the program: decoding it, or building the LLVM module or
+    /// the Cranelift function and generating native code for it. The code may have been
+    /// translated by an earlier run, or ahead of time by a
+    /// [`CompiledProgram`](super::compiled::CompiledProgram). Zero in
+    /// [`RunningMode::Simple`](super::RunningMode::Simple) and
+    /// [`RunningMode::Replicated`](super::RunningMode::Replicated), which run the opcodes.
+    #[serde(rename = "compile_ns", with = "nanos")]
+    pub compile_time: Duration,
+    /// Time spent verifying the LLVM module or the Cranelift function, zero otherwise.
+    #[serde(rename = "verify_ns", with = "nanos")]
+    pub verify_time: Duration,
+    /// Time spent executing the program.
+    #[serde(rename = "execute_ns", with = "nanos")]
+    pub execute_time: Duration,
+}
+
+impl ExecutionReport {
+    /// Registers at the end of the run.
+    pub fn state(&self) -> VmState {
+        VmState {
+            ip: self.ip,
+            acc: self.acc,
+            lc: self.lc,
+            halted: self.halted,
+        }
+    }
+}
+
+mod nanos {
+    use std::time::Duration;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        // More than 584 years, saturate instead of failing
+        serializer.serialize_u64(u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        Ok(Duration::from_nanos(u64::deserialize(deserializer)?))
+    }
+}
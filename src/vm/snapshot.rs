@@ -0,0 +1,128 @@
+//! Snapshots of a paused [`VM`], to resume it later or in another process.
+//!
+//! A snapshot only holds what a run modifies: the registers, the halt flag and
+//! the instruction counter, along with the [fingerprint](Program::fingerprint)
+//! of the program, checked when the snapshot is restored. It can be resumed in
+//! any [`RunningMode`](super::RunningMode), whatever the mode it was taken in.
+//!
+//! The binary form is little-endian:
+//!
+//! ```text
+//! offset  size  field
+//!      0     4  magic number (`VTSS`)
+//!      4     2  format version
+//!      6     8  program fingerprint
+//!     14     4  IP
+//!     18     4  ACC
+//!     22     4  LC
+//!     26     1  halt flag
+//!     27     8  instructions retired
+//! ```
+
+use std::io::Cursor;
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use super::{error::SnapshotError, observer::ExecutionObserver, program::Program, VmState, VM};
+
+pub const MAGIC: [u8; 4] = *b"VTSS";
+pub const VERSION: u16 = 1;
+
+const SIZE: usize = 35;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Snapshot {
+    pub fingerprint: u64,
+    pub state: VmState,
+    pub instructions: u64,
+}
+
+impl Snapshot {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(SIZE);
+        out.extend_from_slice(&MAGIC);
+        // Writing to a `Vec` cannot fail
+        out.write_u16::<LittleEndian>(VERSION).unwrap();
+        out.write_u64::<LittleEndian>(self.fingerprint).unwrap();
+        out.write_u32::<LittleEndian>(self.state.ip).unwrap();
+        out.write_i32::<LittleEndian>(self.state.acc).unwrap();
+        out.write_i32::<LittleEndian>(self.state.lc).unwrap();
+        out.write_u8(self.state.halted as u8).unwrap();
+        out.write_u64::<LittleEndian>(self.instructions).unwrap();
+        out
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SnapshotError> {
+        if !bytes.starts_with(&MAGIC) {
+            return Err(SnapshotError::InvalidMagic);
+        }
+        if bytes.len() < SIZE {
+            return Err(SnapshotError::Truncated { len: bytes.len() });
+        }
+
+        // The length is checked above, reads cannot fail
+        let mut cursor = Cursor::new(&bytes[MAGIC.len()..]);
+        let version = cursor.read_u16::<LittleEndian>().unwrap();
+        if version != VERSION {
+            return Err(SnapshotError::UnsupportedVersion { version });
+        }
+
+        Ok(Snapshot {
+            fingerprint: cursor.read_u64::<LittleEndian>().unwrap(),
+            state: VmState {
+                ip: cursor.read_u32::<LittleEndian>().unwrap(),
+                acc: cursor.read_i32::<LittleEndian>().unwrap(),
+                lc: cursor.read_i32::<LittleEndian>().unwrap(),
+                halted: cursor.read_u8().unwrap() != 0,
+            },
+            instructions: cursor.read_u64::<LittleEndian>().unwrap(),
+        })
+    }
+
+    pub fn to_json(&self) -> String {
+        // The snapshot only holds plain integers, serializing it cannot fail
+        serde_json::to_string(self).unwrap()
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, SnapshotError> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    /// Checks that the snapshot was taken while running `program`.
+    pub fn check(&self, program: &Program) -> Result<(), SnapshotError> {
+        let expected = program.fingerprint();
+        if self.fingerprint != expected {
+            return Err(SnapshotError::ProgramMismatch {
+                expected,
+                found: self.fingerprint,
+            });
+        }
+
+        if self.state.ip as usize > program.data.len() {
+            return Err(SnapshotError::IpOutOfBounds {
+                ip: self.state.ip,
+                len: program.data.len(),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+impl<O: ExecutionObserver> VM<O> {
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            fingerprint: self.running_program.fingerprint(),
+            state: self.state,
+            instructions: self.instructions,
+        }
+    }
+
+    /// Resumes from `snapshot`, which must have been taken while running the same program.
+    pub fn restore(&mut self, snapshot: &Snapshot) -> Result<(), SnapshotError> {
+        snapshot.check(&self.running_program)?;
+        self.state = snapshot.state;
+        self.instructions = snapshot.instructions;
+        Ok(())
+    }
+}
@@ -0,0 +1,134 @@
+//! Static checks over the structure of a [`Program`].
+//!
+//! The scenarios produced by `src/scenario/gen.c` never place a BACK7 in the first 7
+//! instructions, never place a SETL in the 6 instructions before a BACK7 and
+//! always end with a HALT. [`verify`] checks these rules, along with the
+//! opcodes themselves, before a program is executed or JIT compiled.
+
+use super::{opcode::OpCode, program::Program};
+
+/// Distance between a BACK7 and the first instruction of its loop body.
+const BACK7_DISTANCE: usize = 6;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiagnosticKind {
+    /// The byte does not encode any opcode.
+    UnknownOpcode(u8),
+    /// SPILL (0x06) is only injected internally by the JIT.
+    SpillOpcode,
+    /// BACK7 within the first 7 instructions.
+    Back7InPrologue,
+    /// SETL inside the body of the BACK7 at the given offset.
+    SetlInLoopBody { back7: usize },
+    /// The body of this BACK7 contains the BACK7 at the given offset.
+    OverlappingLoops { back7: usize },
+    /// HALT following another HALT, which can never be executed.
+    UnreachableHalt { first_halt: usize },
+    /// The last instruction of the program is not a HALT.
+    MissingFinalHalt,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub offset: usize,
+    pub severity: Severity,
+    pub kind: DiagnosticKind,
+}
+
+impl Diagnostic {
+    fn error(offset: usize, kind: DiagnosticKind) -> Self {
+        Self { offset, severity: Severity::Error, kind }
+    }
+
+    fn warning(offset: usize, kind: DiagnosticKind) -> Self {
+        Self { offset, severity: Severity::Warning, kind }
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        write!(f, "{:04x}: {}: ", self.offset, severity)?;
+
+        match self.kind {
+            DiagnosticKind::UnknownOpcode(opcode) => write!(f, "unknown opcode 0x{:02x}", opcode),
+            DiagnosticKind::SpillOpcode => write!(f, "SPILL is reserved for internal use"),
+            DiagnosticKind::Back7InPrologue => write!(f, "BACK7 in the first 7 instructions"),
+            DiagnosticKind::SetlInLoopBody { back7 } => {
+                write!(f, "SETL in the body of the BACK7 at {:04x}", back7)
+            }
+            DiagnosticKind::OverlappingLoops { back7 } => {
+                write!(f, "loop body overlaps the BACK7 at {:04x}", back7)
+            }
+            DiagnosticKind::UnreachableHalt { first_halt } => {
+                write!(f, "unreachable HALT, the program stops at {:04x}", first_halt)
+            }
+            DiagnosticKind::MissingFinalHalt => write!(f, "the program does not end with HALT"),
+        }
+    }
+}
+
+/// Checks `program`, returning the diagnostics sorted by offset.
+pub fn verify(program: &Program) -> Vec<Diagnostic> {
+    let data = &program.data;
+    let mut diagnostics = vec![];
+    let mut first_halt = None;
+
+    for (offset, instr) in data.iter().enumerate() {
+        match OpCode::try_from(*instr) {
+            Err(_) => diagnostics.push(Diagnostic::error(offset, DiagnosticKind::UnknownOpcode(*instr))),
+            Ok(OpCode::SPILL) => diagnostics.push(Diagnostic::error(offset, DiagnosticKind::SpillOpcode)),
+            Ok(OpCode::HALT) => match first_halt {
+                None => first_halt = Some(offset),
+                Some(first_halt) => diagnostics.push(Diagnostic::warning(
+                    offset,
+                    DiagnosticKind::UnreachableHalt { first_halt },
+                )),
+            },
+            Ok(OpCode::BACK7) if offset <= BACK7_DISTANCE => {
+                diagnostics.push(Diagnostic::error(offset, DiagnosticKind::Back7InPrologue))
+            }
+            Ok(OpCode::BACK7) => {
+                let start = offset - BACK7_DISTANCE;
+                for (body, instr) in data[start..offset].iter().enumerate() {
+                    let body = start + body;
+                    match OpCode::try_from(*instr) {
+                        Ok(OpCode::SETL) => diagnostics.push(Diagnostic::error(
+                            body,
+                            DiagnosticKind::SetlInLoopBody { back7: offset },
+                        )),
+                        Ok(OpCode::BACK7) => diagnostics.push(Diagnostic::warning(
+                            offset,
+                            DiagnosticKind::OverlappingLoops { back7: body },
+                        )),
+                        _ => (),
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    if data.last() != Some(&OpCode::HALT.into()) {
+        diagnostics.push(Diagnostic::error(
+            data.len().saturating_sub(1),
+            DiagnosticKind::MissingFinalHalt,
+        ));
+    }
+
+    diagnostics.sort_by_key(|diagnostic| diagnostic.offset);
+    diagnostics
+}
@@ -0,0 +1,116 @@
+use vt_vm::vm::{
+    self,
+    assembler::{assemble, AsmWarningKind},
+    disassembler::{Decoded, Disassembler, Listing},
+    error::AsmErrorKind,
+    opcode::OpCode,
+    program::Program,
+};
+
+#[test]
+pub fn assemble_program() {
+    let source = "
+        ; same program as `scenario_custom_1`
+        .acc 0
+        .lc 2
+                SETL
+                INC3A
+        body:   INC3A INC3A INC3A   # three increments
+                inc3a INC3A INC3A
+                BACK7 body
+                BACK7
+                HALT
+    ";
+    let assembly = assemble(source).unwrap();
+    assert!(assembly.warnings.is_empty());
+
+    let prog = assembly.program;
+    assert_eq!(prog.data, vec![4, 2, 2, 2, 2, 2, 2, 2, 5, 5, 0]);
+    assert_eq!((prog.initial_acc, prog.initial_lc), (0, 2));
+    assert_eq!(assembly.source_map.len(), prog.data.len());
+    assert_eq!(assembly.source_map[3].line, 7);
+    assert_eq!(assembly.source_map[3].column, 23);
+
+    let mut vm = vm::VM::new(vm::RunningMode::Simple, prog);
+    assert!(vm.run().is_ok());
+}
+
+#[test]
+pub fn assemble_registers() {
+    let prog = assemble(".acc -3\n.lc 0x10\nHALT").unwrap().program;
+    assert_eq!((prog.initial_acc, prog.initial_lc), (-3, 16));
+
+    let prog = assemble(".acc +5\n.lc -0x10\nHALT").unwrap().program;
+    assert_eq!((prog.initial_acc, prog.initial_lc), (5, -16));
+}
+
+#[test]
+pub fn assemble_errors() {
+    let err = assemble("CLRA\n  INC3A JMP\n").unwrap_err();
+    assert_eq!((err.line, err.column), (2, 9));
+    assert_eq!(err.kind, AsmErrorKind::UnknownMnemonic("JMP".to_string()));
+
+    let err = assemble(".acc\n").unwrap_err();
+    assert_eq!(err.kind, AsmErrorKind::MissingOperand(".acc".to_string()));
+
+    for operand in ["--5", "-+5", "+-5", "0x-5", "-0x+5", "5-"] {
+        let err = assemble(&format!(".acc {}", operand)).unwrap_err();
+        assert_eq!(err.kind, AsmErrorKind::InvalidInteger(operand.to_string()));
+    }
+
+    let err = assemble("SPILL").unwrap_err();
+    assert_eq!(err.kind, AsmErrorKind::ReservedOpcode("SPILL".to_string()));
+
+    let err = assemble("BACK7 nowhere").unwrap_err();
+    assert_eq!((err.line, err.column), (1, 7));
+    assert_eq!(err.kind, AsmErrorKind::UndefinedLabel("nowhere".to_string()));
+}
+
+#[test]
+pub fn assemble_loop_body_warning() {
+    let assembly = assemble("top: INC3A INC3A INC3A\nBACK7 top\nHALT").unwrap();
+    assert_eq!(assembly.warnings.len(), 1);
+    assert_eq!(assembly.warnings[0].line, 2);
+    assert_eq!(
+        assembly.warnings[0].kind,
+        AsmWarningKind::LoopBodySize { label: "top".to_string(), size: 3 }
+    );
+}
+
+#[test]
+pub fn disassemble_unknown_bytes() {
+    let prog = Program::new(vec![0xff, 6, 0], 1, 2);
+    let listing = Listing(&prog).to_string();
+    assert!(listing.contains(".byte 0xff"));
+    assert!(listing.contains(".byte 0x06"));
+
+    let back = assemble(&listing).unwrap().program;
+    assert_eq!(back.data, prog.data);
+    assert_eq!((back.initial_acc, back.initial_lc), (1, 2));
+}
+
+#[test]
+pub fn disassemble_loops() {
+    let prog = Program::new(vec![4, 2, 2, 2, 2, 2, 2, 2, 5, 5, 0], 0, 2);
+    let lines: Vec<_> = Disassembler::new(&prog).collect();
+    assert!(lines[2].loop_header && lines[3].loop_header);
+    assert!(!lines[1].loop_header);
+    assert_eq!(lines[8].back7_target, Some(2));
+    assert_eq!(lines[9].back7_target, Some(3));
+    assert_eq!(lines[10].decoded, Decoded::Op(OpCode::HALT));
+
+    let listing = Listing(&prog).to_string();
+    assert!(listing.contains("loop_0002:"));
+    assert!(listing.contains("BACK7 loop_0003"));
+
+    let assembly = assemble(&listing).unwrap();
+    assert!(assembly.warnings.is_empty());
+    assert_eq!(assembly.program.data, prog.data);
+
+    // Labels and offsets share the same radix
+    let prog = Program::new(vec![1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 2, 2, 2, 2, 5, 0], 0, 0);
+    let listing = Listing(&prog).to_string();
+    assert!(listing.contains("loop_000a:"));
+    assert!(listing.contains("BACK7 loop_000a"));
+    assert!(listing.contains("; 0010 -> 000a"));
+}
@@ -0,0 +1,138 @@
+/* Test of the C interface declared in `include/vt_vm.h`, run by `tests/capi.rs`.
+ *
+ * capi_run_tests() returns 0 if every check passes, or the line of the first
+ * failing check.
+ */
+#include <stdint.h>
+#include <stdio.h>
+#include <string.h>
+
+#include "vt_vm.h"
+
+#define CHECK(cond)                                                            \
+  do {                                                                         \
+    if (!(cond)) {                                                             \
+      fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__, __LINE__, #cond); \
+      return __LINE__;                                                         \
+    }                                                                          \
+  } while (0)
+
+// SETL, INC3A x6, BACK7, HALT
+static const uint8_t single_loop[] = {4, 2, 2, 2, 2, 2, 2, 5, 0};
+
+static int check_modes(void) {
+  const uint32_t modes[] = {VT_RUNNING_MODE_SIMPLE,
+                            VT_RUNNING_MODE_NO_OPT_JITTED,
+                            VT_RUNNING_MODE_OPT_JITTED,
+                            VT_RUNNING_MODE_DECODED,
+                            VT_RUNNING_MODE_THREADED,
+                            VT_RUNNING_MODE_CLOSURES,
+                            VT_RUNNING_MODE_SUPERINSTRUCTIONS,
+                            VT_RUNNING_MODE_REPLICATED,
+                            VT_RUNNING_MODE_TEMPLATE_JIT};
+  VtProgram *program = NULL;
+  VtReport report;
+  size_t i;
+
+  CHECK(vt_program_new(single_loop, sizeof(single_loop), 3, 0, &program) ==
+        VT_STATUS_OK);
+
+  for (i = 0; i < sizeof(modes) / sizeof(modes[0]); i++) {
+    VtVm *vm = NULL;
+    CHECK(vt_vm_new(program, modes[i], &vm) == VT_STATUS_OK);
+
+    CHECK(vt_vm_run_with_fuel(vm, 10) == VT_STATUS_OUT_OF_FUEL);
+    CHECK(vt_vm_ip(vm) == 3 && vt_vm_acc(vm) == 27 && vt_vm_lc(vm) == 2);
+    CHECK(!vt_vm_is_halted(vm));
+
+    CHECK(vt_vm_run(vm) == VT_STATUS_OK);
+    CHECK(vt_vm_ip(vm) == 8 && vt_vm_acc(vm) == 57 && vt_vm_lc(vm) == 0);
+    CHECK(vt_vm_is_halted(vm));
+
+    CHECK(vt_vm_report(vm, &report) == VT_STATUS_OK);
+    CHECK(report.halted && report.acc == 57);
+    CHECK(report.instructions == 13);
+    CHECK(report.back_edges_taken == 1 && report.back_edges_not_taken == 1);
+
+    CHECK(vt_vm_reset(vm) == VT_STATUS_OK);
+    CHECK(vt_vm_ip(vm) == 0 && vt_vm_acc(vm) == 3 && !vt_vm_is_halted(vm));
+    vt_vm_free(vm);
+  }
+
+  vt_program_free(program);
+  return 0;
+}
+
+static int check_load(void) {
+  // Legacy format: ACC and LC in little endian, then the code
+  uint8_t bytes[8 + sizeof(single_loop)] = {2, 0, 0, 0, 0, 0, 0, 0};
+  const char *path = "capi_test.vtb";
+  VtProgram *program = NULL;
+  VtVm *vm = NULL;
+  FILE *file;
+
+  memcpy(bytes + 8, single_loop, sizeof(single_loop));
+  CHECK(vt_program_load(bytes, sizeof(bytes), &program) == VT_STATUS_OK);
+  CHECK(vt_last_error_message() == NULL);
+  vt_program_free(program);
+
+  file = fopen(path, "wb");
+  CHECK(file != NULL);
+  CHECK(fwrite(bytes, 1, sizeof(bytes), file) == sizeof(bytes));
+  fclose(file);
+
+  program = NULL;
+  CHECK(vt_program_load_file(path, &program) == VT_STATUS_OK);
+  remove(path);
+
+  CHECK(vt_vm_new(program, VT_RUNNING_MODE_SIMPLE, &vm) == VT_STATUS_OK);
+  CHECK(vt_vm_run(vm) == VT_STATUS_OK);
+  CHECK(vt_vm_acc(vm) == 38);
+  vt_vm_free(vm);
+  vt_program_free(program);
+
+  return 0;
+}
+
+static int check_errors(void) {
+  // INC3A, then an invalid opcode
+  static const uint8_t invalid[] = {2, 7, 0};
+  VtProgram *program = NULL;
+  VtVm *vm = NULL;
+
+  CHECK(vt_program_load(single_loop, 4, &program) ==
+        VT_STATUS_TRUNCATED_HEADER);
+  CHECK(vt_last_error_message() != NULL);
+  CHECK(program == NULL);
+
+  CHECK(vt_program_load_file("does/not/exist", &program) == VT_STATUS_IO);
+  CHECK(vt_program_new(NULL, 4, 0, 0, &program) == VT_STATUS_NULL_POINTER);
+
+  CHECK(vt_program_new(invalid, sizeof(invalid), 0, 0, &program) ==
+        VT_STATUS_OK);
+  CHECK(vt_vm_new(program, 42, &vm) == VT_STATUS_INVALID_ARGUMENT);
+  CHECK(vt_vm_new(program, VT_RUNNING_MODE_SIMPLE, &vm) == VT_STATUS_OK);
+  vt_program_free(program);
+
+  // The VM keeps its own copy of the program
+  CHECK(vt_vm_run(vm) == VT_STATUS_INVALID_OPCODE);
+  CHECK(strstr(vt_last_error_message(), "invalid opcode 0x07") != NULL);
+  CHECK(vt_vm_ip(vm) == 1 && vt_vm_acc(vm) == 3);
+  vt_vm_free(vm);
+
+  CHECK(vt_vm_run(NULL) == VT_STATUS_NULL_POINTER);
+  vt_vm_free(NULL);
+  vt_program_free(NULL);
+
+  return 0;
+}
+
+int capi_run_tests(void) {
+  int line;
+
+  if ((line = check_modes()) != 0)
+    return line;
+  if ((line = check_load()) != 0)
+    return line;
+  return check_errors();
+}
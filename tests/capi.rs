@@ -0,0 +1,26 @@
+use std::os::raw::c_int;
+
+use vt_vm::{
+    capi::VtStatus,
+    vm::{error::VmError, program::Program, RunningMode, VM},
+};
+
+// Add binding for `capi_run_tests` function contained inside `tests/capi.c`.
+extern "C" {
+    pub fn capi_run_tests() -> c_int;
+}
+
+#[test]
+pub fn c_api() {
+    let line = unsafe { capi_run_tests() };
+    assert_eq!(line, 0, "check failed at tests/capi.c:{}", line);
+}
+
+#[test]
+pub fn status_codes() {
+    let mut vm = VM::new(RunningMode::Simple, Program::new(vec![5, 0], 0, 2));
+    let e = vm.run().unwrap_err();
+    assert!(matches!(e, VmError::IpUnderflow { .. }));
+    assert_eq!(VtStatus::from(&e), VtStatus::IpUnderflow);
+    assert_eq!(VtStatus::from(&e) as u32, 22);
+}
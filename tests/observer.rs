@@ -0,0 +1,83 @@
+use vt_vm::vm::{
+    observer::{ExecutionObserver, Register},
+    opcode::OpCode,
+    program::Program,
+    ExecutionOutcome, RunningMode, VmState, VM,
+};
+
+#[derive(Debug, Default)]
+struct Recorder {
+    opcodes: Vec<OpCode>,
+    taken: Vec<(u32, u32)>,
+    not_taken: Vec<u32>,
+    writes: Vec<(u32, Register, i32)>,
+    halted: Option<VmState>,
+}
+
+impl ExecutionObserver for Recorder {
+    fn before_instruction(&mut self, _state: &VmState, opcode: OpCode) {
+        self.opcodes.push(opcode);
+    }
+
+    fn back_edge_taken(&mut self, ip: u32, target: u32) {
+        self.taken.push((ip, target));
+    }
+
+    fn back_edge_not_taken(&mut self, ip: u32) {
+        self.not_taken.push(ip);
+    }
+
+    fn register_write(&mut self, ip: u32, register: Register, value: i32) {
+        self.writes.push((ip, register, value));
+    }
+
+    fn halt(&mut self, state: &VmState) {
+        self.halted = Some(*state);
+    }
+}
+
+// SETL, INC3A x6, BACK7, HALT
+fn single_loop() -> Program {
+    Program::new(vec![4, 2, 2, 2, 2, 2, 2, 5, 0], 3, 0)
+}
+
+#[test]
+pub fn observer_callbacks() {
+    let modes = [RunningMode::Simple, RunningMode::NoOptJitted, RunningMode::OptJitted];
+    for mode in modes {
+        let mut vm = VM::builder(single_loop()).mode(mode.clone()).build_with_observer(Recorder::default());
+        assert_eq!(vm.run(), Ok(ExecutionOutcome::Halted), "{:?}", mode);
+
+        let instructions = vm.instructions();
+        let final_state = vm.state();
+        let recorder = vm.into_observer();
+
+        // Observed VMs are interpreted whatever their mode
+        assert_eq!(recorder.opcodes.len() as u64, instructions, "{:?}", mode);
+        assert_eq!(recorder.opcodes.first(), Some(&OpCode::SETL), "{:?}", mode);
+        assert_eq!(recorder.opcodes.last(), Some(&OpCode::HALT), "{:?}", mode);
+
+        assert_eq!(recorder.taken, vec![(7, 1), (7, 1)], "{:?}", mode);
+        assert_eq!(recorder.not_taken, vec![7], "{:?}", mode);
+
+        let acc_writes = recorder.writes.iter().filter(|(_, register, _)| *register == Register::Acc).count();
+        assert_eq!(acc_writes, 18, "{:?}", mode);
+        assert_eq!(recorder.writes.first(), Some(&(0, Register::Lc, 3)), "{:?}", mode);
+        assert_eq!(recorder.writes.last(), Some(&(7, Register::Lc, 0)), "{:?}", mode);
+
+        assert_eq!(recorder.halted, Some(final_state), "{:?}", mode);
+    }
+}
+
+#[test]
+pub fn observer_by_reference() {
+    let mut recorder = Recorder::default();
+    let mut vm = VM::new(RunningMode::Simple, single_loop()).with_observer(&mut recorder);
+    vm.run_with_fuel(8).unwrap();
+    vm.run().unwrap();
+    drop(vm);
+
+    // Both runs report to the same observer
+    assert_eq!(recorder.opcodes.len(), 23);
+    assert_eq!(recorder.taken.len(), 2);
+}
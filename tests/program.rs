@@ -0,0 +1,100 @@
+use vt_vm::vm::{
+    error::ProgramLoadError,
+    program::{
+        container, ExpectedRegisters, LoadOptions, Program, ProgramInfo, SourceLocation,
+    },
+};
+
+#[test]
+pub fn load_from_bytes() {
+    let bytes = [3, 0, 0, 0, 0xfe, 0xff, 0xff, 0xff, 2, 3, 0];
+    let prog = Program::from_bytes(&bytes).unwrap();
+    assert_eq!(prog.initial_acc, 3);
+    assert_eq!(prog.initial_lc, -2);
+    assert_eq!(prog.data, vec![2, 3, 0]);
+}
+
+#[test]
+pub fn load_from_reader() {
+    let bytes: &[u8] = &[1, 0, 0, 0, 2, 0, 0, 0, 0];
+    let prog = Program::from_reader(bytes).unwrap();
+    assert_eq!((prog.initial_acc, prog.initial_lc), (1, 2));
+    assert_eq!(prog.data, vec![0]);
+}
+
+#[test]
+pub fn load_truncated_header() {
+    let res = Program::from_bytes(&[1, 0, 0]);
+    assert!(matches!(res, Err(ProgramLoadError::TruncatedHeader { len: 3 })));
+}
+
+#[test]
+pub fn load_missing_file() {
+    let res = Program::read_from_file("does/not/exist.bin");
+    assert!(matches!(res, Err(ProgramLoadError::Io(_))));
+}
+
+#[test]
+pub fn load_validate_opcodes() {
+    let bytes = [0, 0, 0, 0, 0, 0, 0, 0, 2, 0x42, 0];
+    assert!(Program::from_bytes(&bytes).is_ok());
+
+    let res = LoadOptions::new().validate_opcodes(true).load_bytes(&bytes);
+    assert!(matches!(
+        res,
+        Err(ProgramLoadError::InvalidOpcode { opcode: 0x42, offset: 9 })
+    ));
+}
+
+fn annotated_program() -> (Program, ProgramInfo) {
+    let prog = Program::new(vec![4, 2, 2, 2, 2, 2, 2, 2, 5, 5, 0], 0, 2);
+    let info = ProgramInfo {
+        name: Some("custom_1".to_string()),
+        metadata: vec![("seed".to_string(), "1".to_string())],
+        expected: Some(ExpectedRegisters { acc: 18, lc: -1 }),
+        source_map: vec![SourceLocation { offset: 0, line: 1, column: 1 }],
+    };
+    (prog, info)
+}
+
+#[test]
+pub fn container_round_trip() {
+    let (prog, info) = annotated_program();
+    let bytes = prog.to_bytes_with_info(&info);
+    assert!(container::is_container(&bytes));
+    assert_eq!(Program::from_bytes_with_info(&bytes).unwrap(), (prog.clone(), info));
+    assert_eq!(Program::from_bytes(&bytes).unwrap(), prog);
+
+    let bytes = prog.to_bytes();
+    assert_eq!(Program::from_bytes_with_info(&bytes).unwrap(), (prog, ProgramInfo::default()));
+}
+
+#[test]
+pub fn container_checksum_mismatch() {
+    let (prog, info) = annotated_program();
+    let mut bytes = prog.to_bytes_with_info(&info);
+    let last = bytes.len() - 1;
+    bytes[last] ^= 0xff;
+    let res = Program::from_bytes(&bytes);
+    assert!(matches!(res, Err(ProgramLoadError::ChecksumMismatch { .. })));
+}
+
+#[test]
+pub fn container_unsupported_version() {
+    let (prog, info) = annotated_program();
+    let mut bytes = prog.to_bytes_with_info(&info);
+    bytes[4] = 0x7f;
+    let res = Program::from_bytes(&bytes);
+    assert!(matches!(
+        res,
+        Err(ProgramLoadError::UnsupportedVersion { version: 0x7f })
+    ));
+}
+
+#[test]
+pub fn legacy_round_trip() {
+    let prog = Program::new(vec![2, 2, 3, 0], 7, 3);
+    let bytes = prog.to_legacy_bytes();
+    assert!(!container::is_container(&bytes));
+    assert_eq!(Program::from_bytes(&bytes).unwrap(), prog);
+}
@@ -1,21 +1,16 @@
-use std::{
-    i32,
-    os::raw::{c_char, c_int}
-};
+use std::i32;
 
-use vt_vm::vm::{self, program::Program};
-
-// Add binding for `init` function contained inside `tests/gen.c`.
-extern "C" {
-    pub fn init(
-        buf: *mut c_char,
-        size: c_int,
-        prob: *mut c_int,
-        seed: c_int,
-        r_a: *mut c_int,
-        r_l: *mut c_int,
-    );
-}
+use vt_vm::{
+    scenario,
+    vm::{
+        self, assembler,
+        compiled::CompiledProgram,
+        error::{ScenarioError, VmError},
+        report::ExecutionReport,
+        program::Program,
+        verifier::{self, DiagnosticKind, Severity},
+    },
+};
 
 #[derive(Debug, serde::Serialize)]
 struct Stats {
@@ -27,57 +22,76 @@ struct Stats {
     iterations: u32
 }
 
+/// Every running mode, the Cranelift ones need the `cranelift` feature.
+const ALL_MODES: &[vm::RunningMode] = &[
+    vm::RunningMode::Simple,
+    vm::RunningMode::NoOptJitted,
+    vm::RunningMode::OptJitted,
+    vm::RunningMode::Decoded,
+    vm::RunningMode::Threaded,
+    vm::RunningMode::Closures,
+    vm::RunningMode::Superinstructions,
+    vm::RunningMode::Replicated,
+    vm::RunningMode::TemplateJit,
+    #[cfg(feature = "cranelift")]
+    vm::RunningMode::NoOptCranelift,
+    #[cfg(feature = "cranelift")]
+    vm::RunningMode::OptCranelift,
+];
+
 /// Returns a pseudo-random scenario generated by the given C program.
-fn generate_scenario(size: usize, seed: i32, mut probs: [i32; 5]) -> vm::program::Program {
-    let mut r_a: i32 = 0;
-    let mut r_l: i32 = 0;
-
-    let mut data: Vec<u8> = Vec::new();
-    data.resize(size, 0);
-
-    unsafe {
-        init(
-            data.as_mut_ptr() as *mut i8,
-            size as i32,
-            probs.as_mut_ptr(),
-            seed,
-            &mut r_a,
-            &mut r_l,
-        )
-    }
+fn generate_scenario(size: usize, seed: i32, probs: [i32; 5]) -> vm::program::Program {
+    scenario::generate(size, seed, probs).unwrap()
+}
 
-    Program::new(data, r_a, r_l)
+#[test]
+pub fn scenario_generator() {
+    let prog = generate_scenario(100, 7, [1, 1, 1, 1, 1]);
+    assert_eq!(prog, generate_scenario(100, 7, [1, 1, 1, 1, 1]));
+    assert_eq!(prog.data.len(), 100);
+    assert_eq!(prog.data.last(), Some(&0));
+
+    assert_eq!(scenario::generate(0, 1, [1, 1, 1, 1, 1]), Err(ScenarioError::EmptyProgram));
+    assert!(matches!(
+        scenario::generate(100, 1, [0, 0, 0, 0, 1]),
+        Err(ScenarioError::InvalidProbabilities { .. })
+    ));
+    assert!(scenario::generate(100, 1, [1, -1, 0, 0, 0]).is_err());
+    // Only SETL and BACK7 would make the generator loop forever
+    assert!(scenario::generate(100, 1, [0, 0, 0, 1, 1]).is_err());
+    assert!(scenario::generate(100, 1, [i32::MAX, 1, 0, 0, 0]).is_err());
+    assert!(scenario::generate(100, 1, [i32::MAX, 0, 0, 0, 0]).is_ok());
 }
 
 #[test]
 pub fn scenario_1() {
     let prog = generate_scenario(10_000, 1, [0, 1, 0, 0, 0]);
-    let vm = vm::VM::new(vm::RunningMode::Simple, prog);
-    vm.run();
+    let mut vm = vm::VM::new(vm::RunningMode::Simple, prog);
+    vm.run().unwrap();
     println!("{}", vm);
 }
 
 #[test]
 pub fn scenario_2() {
     let prog = generate_scenario(10_000, 1, [1, 1, 1, 0, 0]);
-    let vm = vm::VM::new(vm::RunningMode::Simple, prog);
-    vm.run();
+    let mut vm = vm::VM::new(vm::RunningMode::Simple, prog);
+    vm.run().unwrap();
     println!("{}", vm);
 }
 
 #[test]
 pub fn scenario_3() {
     let prog = generate_scenario(10_000, 1, [1, 9, 1, 5, 5]);
-    let vm = vm::VM::new(vm::RunningMode::Simple, prog);
-    vm.run();
+    let mut vm = vm::VM::new(vm::RunningMode::Simple, prog);
+    vm.run().unwrap();
     println!("{}", vm);
 }
 
 #[test]
 pub fn scenario_4() {
     let prog = generate_scenario(50_000, 1, [1, 9, 1, 5, 5]);
-    let vm = vm::VM::new(vm::RunningMode::Simple, prog);
-    vm.run();
+    let mut vm = vm::VM::new(vm::RunningMode::Simple, prog);
+    vm.run().unwrap();
     println!("{}", vm);
 }
 
@@ -89,8 +103,8 @@ pub fn scenario_custom_0() {
         initial_lc: 0, 
         filename: None 
     };
-    let vm = vm::VM::new(vm::RunningMode::Simple, prog);
-    vm.run();
+    let mut vm = vm::VM::new(vm::RunningMode::Simple, prog);
+    vm.run().unwrap();
     println!("{}", vm);
 }
 
@@ -102,15 +116,540 @@ pub fn scenario_custom_1() {
         initial_lc: 2, 
         filename: None 
     };
-    let vm = vm::VM::new(vm::RunningMode::Simple, prog);
-    vm.run();
+    let mut vm = vm::VM::new(vm::RunningMode::Simple, prog);
+    vm.run().unwrap();
     println!("{}", vm);
 }
 
+#[test]
+pub fn disassemble_round_trip() {
+    let prog = generate_scenario(10_000, 1, [1, 9, 1, 5, 5]);
+    let listing = prog.to_string();
+    let assembly = assembler::assemble(&listing).unwrap();
+    assert!(assembly.warnings.is_empty());
+    assert_eq!(assembly.program.data, prog.data);
+    assert_eq!(assembly.program.initial_acc, prog.initial_acc);
+    assert_eq!(assembly.program.initial_lc, prog.initial_lc);
+}
+
+#[test]
+pub fn verify_scenarios() {
+    let scenarios = [
+        generate_scenario(10_000, 1, [0, 1, 0, 0, 0]),
+        generate_scenario(10_000, 1, [1, 1, 1, 0, 0]),
+        generate_scenario(10_000, 1, [1, 9, 1, 5, 5]),
+        generate_scenario(50_000, 1, [1, 9, 1, 5, 5]),
+    ];
+
+    for prog in scenarios {
+        let errors: Vec<_> = verifier::verify(&prog)
+            .into_iter()
+            .filter(|diagnostic| diagnostic.is_error())
+            .collect();
+        assert!(errors.is_empty(), "{:?}", errors);
+    }
+}
+
+#[test]
+pub fn verify_rejects_program() {
+    let prog = Program::new(vec![2, 4, 2, 2, 2, 2, 2, 5, 6, 0, 0], 0, 0);
+    let diagnostics = verifier::verify(&prog);
+    let kinds: Vec<_> = diagnostics.iter().map(|d| (d.offset, d.severity, d.kind)).collect();
+    assert_eq!(
+        kinds,
+        vec![
+            (1, Severity::Error, DiagnosticKind::SetlInLoopBody { back7: 7 }),
+            (8, Severity::Error, DiagnosticKind::SpillOpcode),
+            (10, Severity::Warning, DiagnosticKind::UnreachableHalt { first_halt: 9 }),
+        ]
+    );
+
+    let err = vm::VM::new_verified(vm::RunningMode::Simple, prog).unwrap_err();
+    assert_eq!(err.diagnostics.len(), 2);
+}
+
+#[test]
+pub fn error_invalid_opcode() {
+    let prog = Program::new(vec![2, 2, 0xff, 0], 1, 0);
+    let mut vm = vm::VM::new(vm::RunningMode::Simple, prog);
+    assert_eq!(vm.run(), Err(VmError::InvalidOpcode { opcode: 0xff, ip: 2, acc: 7, lc: 0 }));
+}
+
+#[test]
+pub fn error_missing_halt() {
+    let prog = Program::new(vec![2, 3], 0, 0);
+    let mut vm = vm::VM::new(vm::RunningMode::Simple, prog);
+    assert_eq!(vm.run(), Err(VmError::IpOutOfBounds { ip: 2, acc: 2, lc: 0 }));
+}
+
+#[test]
+pub fn error_back7_underflow() {
+    let prog = Program::new(vec![2, 2, 5, 0], 0, 3);
+    let mut vm = vm::VM::new(vm::RunningMode::Simple, prog);
+    assert_eq!(vm.run(), Err(VmError::IpUnderflow { ip: 2, acc: 6, lc: 3 }));
+}
+
+/// Runs `scenarios` in every mode of [`ALL_MODES`], with each overflow mode and each of the
+/// `fuels`, and checks the outcome, the registers and the counters against the simple
+/// interpreter. The JIT modes check the whole program before running it: a program they
+/// reject must fail at the same instruction when interpreted.
+fn assert_same_as_simple(scenarios: &[Program], fuels: &[Option<u64>]) {
+    let overflow_modes = [vm::OverflowMode::Wrapping, vm::OverflowMode::Saturating, vm::OverflowMode::Trap];
+    let run = |vm: &mut vm::VM, fuel: Option<u64>| match fuel {
+        Some(fuel) => vm.run_with_fuel(fuel),
+        None => vm.run(),
+    };
+
+    for mode in ALL_MODES {
+        for prog in scenarios {
+            for overflow_mode in overflow_modes {
+                let compiled = match CompiledProgram::compile_with_overflow_mode(prog, mode.clone(), overflow_mode) {
+                    Ok(compiled) => compiled,
+                    Err(err) => {
+                        let mut expected = vm::VM::builder(prog.clone()).overflow_mode(overflow_mode).build();
+                        let expected = expected.run().unwrap_err();
+                        assert_eq!(std::mem::discriminant(&err), std::mem::discriminant(&expected), "{:?}", mode);
+                        assert_eq!(err.ip(), expected.ip(), "{:?} {:?}", mode, overflow_mode);
+                        continue;
+                    }
+                };
+
+                for &fuel in fuels {
+                    let mut expected = vm::VM::builder(prog.clone()).overflow_mode(overflow_mode).build();
+                    let expected_result = run(&mut expected, fuel);
+
+                    let mut vm = compiled.builder().build();
+                    let context = format!("{:?} {:?} {:?}", mode, overflow_mode, fuel);
+                    assert_eq!(run(&mut vm, fuel), expected_result, "{}", context);
+                    assert_eq!(vm.state(), expected.state(), "{}", context);
+                    assert_eq!(vm.instructions(), expected.instructions(), "{}", context);
+                    let (report, expected) = (vm.report(), expected.report());
+                    assert_eq!(report.back_edges_taken, expected.back_edges_taken, "{}", context);
+                    assert_eq!(report.back_edges_not_taken, expected.back_edges_not_taken, "{}", context);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+pub fn same_state_as_simple() {
+    let scenarios = [
+        // Cranelift takes seconds to compile 10_000 instructions in debug builds, minutes for 50_000
+        generate_scenario(2_000, 1, [0, 1, 0, 0, 0]),
+        generate_scenario(2_000, 1, [1, 1, 1, 0, 0]),
+        generate_scenario(2_000, 1, [1, 9, 1, 5, 5]),
+        generate_scenario(10_000, 1, [1, 9, 1, 5, 5]),
+        // INC3A, invalid opcode
+        Program::new(vec![2, 7, 0], 0, 0),
+        // INC3A, INC3A, no HALT
+        Program::new(vec![2, 2], 0, 0),
+        // SETL, INC3A, BACK7 jumping before the start
+        Program::new(vec![4, 2, 5, 0], 4, 0),
+        // DECA at i32::MIN
+        Program::new(vec![3, 0], i32::MIN, 0),
+        // INC3A x2, DECA x2 overflowing in the middle of the run, CLRA after it
+        Program::new(vec![2, 2, 3, 3, 0], i32::MAX - 4, 0),
+        Program::new(vec![2, 2, 1, 3, 3, 0], i32::MAX - 4, 0),
+        // SETL, INC3A x6, BACK7, HALT
+        Program::new(vec![4, 2, 2, 2, 2, 2, 2, 5, 0], 3, 0),
+    ];
+    assert_same_as_simple(&scenarios, &[None, Some(5), Some(1_000)]);
+
+    // CLRA x6, BACK7 decrementing LC from i32::MIN, which loops 2^31 times when wrapping
+    let prog = Program::new(vec![1, 1, 1, 1, 1, 1, 5, 0], 0, i32::MIN);
+    assert_same_as_simple(&[prog], &[Some(5), Some(1_000)]);
+}
+
+#[test]
+pub fn superinstructions() {
+    // SETL, INC3A x6, BACK7, HALT: SETL ADD 18, ADD 18 x2 from the back edge, BACK7 x3
+    // and HALT dispatch 7 times
+    let prog = Program::new(vec![4, 2, 2, 2, 2, 2, 2, 5, 0], 3, 0);
+    let mut vm = vm::VM::new(vm::RunningMode::Superinstructions, prog.clone());
+    vm.run().unwrap();
+    assert!(vm.to_string().contains("ip: 8, acc: 57, lc: 0"));
+    assert_eq!((vm.report().instructions, vm.report().dispatches_saved), (23, 16));
+
+    let mut vm = vm::VM::new(vm::RunningMode::Simple, prog);
+    vm.run().unwrap();
+    assert_eq!(vm.report().dispatches_saved, 0);
+
+    // CLRA, INC3A x2, SETL, INC3A, DECA, HALT: SET 6, SETL ADD 2 and HALT
+    let prog = Program::new(vec![1, 2, 2, 4, 2, 3, 0], 0, 0);
+    let mut vm = vm::VM::new(vm::RunningMode::Superinstructions, prog);
+    vm.run().unwrap();
+    assert!(vm.to_string().contains("ip: 6, acc: 8, lc: 6"));
+    assert_eq!((vm.report().instructions, vm.report().dispatches_saved), (7, 4));
+
+    // Resuming in the middle of a superinstruction interprets up to the next one
+    let prog = generate_scenario(10_000, 1, [1, 9, 1, 5, 5]);
+    let mut expected = vm::VM::new(vm::RunningMode::Simple, prog.clone());
+    expected.run().unwrap();
+    let mut vm = vm::VM::new(vm::RunningMode::Superinstructions, prog);
+    while vm.run_with_fuel(997).unwrap() != vm::ExecutionOutcome::Halted {}
+    assert_eq!(vm.state(), expected.state());
+    assert_eq!(vm.instructions(), expected.instructions());
+}
+
+#[test]
+pub fn template_jit() {
+    // Programs are checked before generating code, as with LLVM
+    let mut vm = vm::VM::new(vm::RunningMode::TemplateJit, Program::new(vec![2, 7, 0], 0, 0));
+    assert_eq!(vm.run(), Err(VmError::InvalidOpcode { opcode: 7, ip: 1, acc: 0, lc: 0 }));
+    let mut vm = vm::VM::new(vm::RunningMode::TemplateJit, Program::new(vec![2, 2], 0, 0));
+    assert_eq!(vm.run(), Err(VmError::IpOutOfBounds { ip: 2, acc: 0, lc: 0 }));
+}
+
+#[test]
+#[cfg(feature = "cranelift")]
+pub fn cranelift() {
+    let prog = generate_scenario(1_000, 1, [1, 9, 1, 5, 5]);
+
+    for mode in [vm::RunningMode::NoOptCranelift, vm::RunningMode::OptCranelift] {
+        // The function is verified apart from its compilation, as with LLVM
+        let mut vm = vm::VM::new(mode.clone(), prog.clone());
+        vm.run().unwrap();
+        assert!(vm.report().compile_time > std::time::Duration::ZERO, "{:?}", mode);
+        assert!(vm.report().verify_time > std::time::Duration::ZERO, "{:?}", mode);
+
+        let mut vm = vm::VM::new(mode.clone(), Program::new(vec![2, 7, 0], 0, 0));
+        assert_eq!(vm.run(), Err(VmError::InvalidOpcode { opcode: 7, ip: 1, acc: 0, lc: 0 }));
+    }
+}
+
+#[test]
+pub fn overflow_modes() {
+    // INC3A, HALT
+    let prog = Program::new(vec![2, 0], i32::MAX - 1, 0);
+
+    for mode in ALL_MODES {
+        let mut vm = vm::VM::new(mode.clone(), prog.clone()).with_overflow_mode(vm::OverflowMode::Wrapping);
+        vm.run().unwrap();
+        assert!(vm.to_string().contains(&format!("acc: {},", i32::MIN + 1)), "{:?}", mode);
+
+        let mut vm = vm::VM::new(mode.clone(), prog.clone()).with_overflow_mode(vm::OverflowMode::Saturating);
+        vm.run().unwrap();
+        assert!(vm.to_string().contains(&format!("acc: {},", i32::MAX)), "{:?}", mode);
+
+        let mut vm = vm::VM::new(mode.clone(), prog.clone()).with_overflow_mode(vm::OverflowMode::Trap);
+        assert_eq!(
+            vm.run(),
+            Err(VmError::ArithmeticOverflow { ip: 0, acc: i32::MAX - 1, lc: 0 }),
+            "{:?}",
+            mode
+        );
+    }
+
+    // INC3A x2, DECA x2, HALT: only the first INC3A of the block is retired before the trap
+    let prog = Program::new(vec![2, 2, 3, 3, 0], i32::MAX - 4, 0);
+
+    for mode in ALL_MODES {
+        for fuel in [None, Some(10)] {
+            let mut vm = vm::VM::new(mode.clone(), prog.clone()).with_overflow_mode(vm::OverflowMode::Trap);
+            let result = match fuel {
+                Some(fuel) => vm.run_with_fuel(fuel),
+                None => vm.run(),
+            };
+            assert_eq!(
+                result,
+                Err(VmError::ArithmeticOverflow { ip: 1, acc: i32::MAX - 1, lc: 0 }),
+                "{:?}",
+                mode
+            );
+            assert_eq!(vm.instructions(), 1, "{:?} {:?}", mode, fuel);
+        }
+    }
+}
+
+#[test]
+pub fn fuel_limit() {
+    // SETL, INC3A x6, BACK7, HALT: 23 instructions retired
+    let prog = Program::new(vec![4, 2, 2, 2, 2, 2, 2, 5, 0], 3, 0);
+
+    let mut vm = vm::VM::new(vm::RunningMode::Simple, prog.clone());
+    assert_eq!(vm.run_with_fuel(8), Ok(vm::ExecutionOutcome::OutOfFuel { ip: 1, acc: 21, lc: 2 }));
+
+    for mode in ALL_MODES {
+        for fuel in 0..23 {
+            let mut expected = vm::VM::new(vm::RunningMode::Simple, prog.clone());
+            let expected = expected.run_with_fuel(fuel).unwrap();
+
+            let mut vm = vm::VM::new(mode.clone(), prog.clone());
+            assert_eq!(vm.run_with_fuel(fuel), Ok(expected), "{:?}", mode);
+        }
+
+        // Resume the same VM until it halts
+        let mut vm = vm::VM::new(mode.clone(), prog.clone());
+        let mut runs = 1;
+        while vm.run_with_fuel(5).unwrap() != vm::ExecutionOutcome::Halted {
+            runs += 1;
+        }
+        assert_eq!(runs, 5, "{:?}", mode);
+        assert!(vm.to_string().contains("ip: 8, acc: 57, lc: 0"), "{:?}", mode);
+        assert_eq!(vm.run_with_fuel(0), Ok(vm::ExecutionOutcome::Halted), "{:?}", mode);
+    }
+}
+
+#[test]
+pub fn interrupt() {
+    // SETL, INC3A x6, BACK7, HALT: loops 1 billion times
+    let prog = Program::new(vec![4, 2, 2, 2, 2, 2, 2, 5, 0], 1_000_000_000, 0);
+
+    for mode in ALL_MODES {
+        let mut vm = vm::VM::new(mode.clone(), prog.clone());
+
+        // A request made before the run stops it before the first instruction
+        vm.interrupt_handle().interrupt();
+        assert_eq!(
+            vm.run(),
+            Ok(vm::ExecutionOutcome::Interrupted { ip: 0, acc: 1_000_000_000, lc: 0 }),
+            "{:?}",
+            mode
+        );
+
+        let handle = vm.interrupt_handle();
+        let interrupter = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            handle.interrupt();
+        });
+        let outcome = vm.run().unwrap();
+        interrupter.join().unwrap();
+        let (ip, lc) = match outcome {
+            vm::ExecutionOutcome::Interrupted { ip, lc, .. } => (ip, lc),
+            outcome => panic!("{:?}: {:?}", mode, outcome),
+        };
+        assert!(ip <= 7 && lc > 0 && lc < 1_000_000_000, "{:?}: {:?}", mode, outcome);
+
+        // The VM resumes from where it stopped
+        assert!(matches!(
+            vm.run_with_timeout(std::time::Duration::from_millis(20)),
+            Ok(vm::ExecutionOutcome::Interrupted { .. })
+        ));
+        assert!(matches!(vm.run_with_fuel(100), Ok(vm::ExecutionOutcome::OutOfFuel { .. })));
+    }
+
+    // A program halting before the deadline is not interrupted
+    let prog = Program::new(vec![4, 2, 2, 2, 2, 2, 2, 5, 0], 3, 0);
+    for mode in ALL_MODES {
+        let mut vm = vm::VM::new(mode.clone(), prog.clone());
+        assert_eq!(
+            vm.run_with_timeout(std::time::Duration::from_secs(10)),
+            Ok(vm::ExecutionOutcome::Halted),
+            "{:?}",
+            mode
+        );
+        assert!(!vm.interrupt_handle().is_interrupted());
+    }
+}
+
+#[test]
+pub fn step_and_run_until() {
+    // SETL, INC3A x6, BACK7, HALT
+    let prog = Program::new(vec![4, 2, 2, 2, 2, 2, 2, 5, 0], 3, 0);
+    let mut vm = vm::VM::new(vm::RunningMode::Simple, prog);
+
+    assert_eq!(vm.step(), Ok(vm::StepResult::Continue));
+    assert_eq!((vm.ip(), vm.acc(), vm.lc()), (1, 3, 3));
+
+    assert_eq!(vm.run_until(|vm| vm.ip() == 7 && vm.lc() == 1), Ok(vm::StepResult::Continue));
+    assert_eq!((vm.ip(), vm.acc(), vm.lc()), (7, 57, 1));
+
+    assert_eq!(vm.step(), Ok(vm::StepResult::Continue));
+    assert_eq!(vm.step(), Ok(vm::StepResult::Halted));
+    assert_eq!(vm.step(), Ok(vm::StepResult::Halted));
+    assert!(vm.is_halt());
+    assert_eq!(vm.run_until(|_| true), Ok(vm::StepResult::Halted));
+
+    vm.reset();
+    assert_eq!((vm.ip(), vm.acc(), vm.lc(), vm.is_halt()), (0, 3, 0, false));
+    assert_eq!(vm.run(), Ok(vm::ExecutionOutcome::Halted));
+    assert_eq!((vm.ip(), vm.acc(), vm.lc()), (8, 57, 0));
+
+    // Stepping a generated scenario reaches the same state as running it
+    let prog = generate_scenario(10_000, 1, [1, 9, 1, 5, 5]);
+    let mut expected = vm::VM::new(vm::RunningMode::Simple, prog.clone());
+    expected.run().unwrap();
+
+    let mut vm = vm::VM::new(vm::RunningMode::Simple, prog);
+    while vm.step().unwrap() == vm::StepResult::Continue {}
+    assert_eq!(vm, expected);
+}
+
+#[test]
+pub fn builder() {
+    // SETL, INC3A x6, BACK7, HALT
+    let prog = Program::new(vec![4, 2, 2, 2, 2, 2, 2, 5, 0], 3, 0);
+
+    let mut vm = vm::VM::builder(prog.clone()).acc(1).build();
+    vm.run().unwrap();
+    assert_eq!((vm.ip(), vm.acc(), vm.lc()), (8, 19, 0));
+
+    // Skip SETL, L is given by the user
+    let mut vm = vm::VM::builder(prog.clone()).ip(1).acc(0).lc(2).build();
+    vm.run().unwrap();
+    assert_eq!((vm.ip(), vm.acc(), vm.lc()), (8, 36, 0));
+    vm.reset();
+    assert_eq!((vm.ip(), vm.acc(), vm.lc()), (1, 0, 2));
+
+    let mut vm = vm::VM::builder(prog.clone())
+        .acc(i32::MAX)
+        .overflow_mode(vm::OverflowMode::Trap)
+        .build();
+    assert_eq!(vm.run(), Err(VmError::ArithmeticOverflow { ip: 1, acc: i32::MAX, lc: i32::MAX }));
+
+    // Limits apply to every run
+    let mut vm = vm::VM::builder(prog.clone()).fuel(10).build();
+    assert_eq!(vm.run(), Ok(vm::ExecutionOutcome::OutOfFuel { ip: 3, acc: 27, lc: 2 }));
+    assert_eq!(vm.run(), Ok(vm::ExecutionOutcome::OutOfFuel { ip: 6, acc: 54, lc: 1 }));
+
+    let mut vm = vm::VM::builder(prog)
+        .acc(1_000_000_000)
+        .timeout(std::time::Duration::from_millis(20))
+        .build();
+    assert!(matches!(vm.run(), Ok(vm::ExecutionOutcome::Interrupted { .. })));
+}
+
+#[test]
+pub fn batch_runner() {
+    use vm::batch::{BatchRunner, Job};
+
+    let scenarios = [
+        generate_scenario(10_000, 1, [0, 1, 0, 0, 0]),
+        generate_scenario(10_000, 1, [1, 1, 1, 0, 0]),
+        generate_scenario(10_000, 1, [1, 9, 1, 5, 5]),
+        generate_scenario(50_000, 1, [1, 9, 1, 5, 5]),
+    ];
+
+    let mut jobs = vec![];
+    for prog in &scenarios {
+        jobs.push(Job::new(prog.clone(), vm::RunningMode::Simple));
+        let state = vm::VmState { acc: 7, lc: 0, ..Default::default() };
+        jobs.push(Job::new(prog.clone(), vm::RunningMode::Simple).with_state(state));
+    }
+
+    let results = BatchRunner::new().threads(3).run(jobs.clone());
+    assert_eq!(results.len(), jobs.len());
+
+    for (job, result) in jobs.into_iter().zip(results) {
+        let mut builder = vm::VM::builder(job.program).mode(job.mode);
+        if let Some(state) = job.state {
+            builder = builder.state(state);
+        }
+        let mut vm = builder.build();
+        assert_eq!(result.outcome, vm.run());
+        assert_eq!(result.state, vm.state());
+        assert!(result.state.halted);
+    }
+}
+
+#[test]
+pub fn execution_report() {
+    // SETL, INC3A x6, BACK7, HALT
+    let prog = Program::new(vec![4, 2, 2, 2, 2, 2, 2, 5, 0], 3, 0);
+
+    for mode in ALL_MODES {
+        let mut vm = vm::VM::new(mode.clone(), prog.clone());
+        vm.run_with_fuel(10).unwrap();
+        let report = vm.report();
+        assert_eq!(report.state(), vm.state(), "{:?}", mode);
+        assert_eq!((report.instructions, report.back_edges_taken, report.back_edges_not_taken), (10, 1, 0), "{:?}", mode);
+
+        // Counters only cover the last run
+        vm.run().unwrap();
+        let report = vm.report();
+        assert_eq!((report.ip, report.acc, report.lc, report.halted), (8, 57, 0, true), "{:?}", mode);
+        assert_eq!((report.instructions, report.back_edges_taken, report.back_edges_not_taken), (13, 1, 1), "{:?}", mode);
+        assert_eq!(report.execute_time, vm.running_time, "{:?}", mode);
+
+        // Resuming after the fall-through of the last BACK7 does not count it again
+        let mut vm = vm::VM::new(mode.clone(), prog.clone());
+        vm.run_with_fuel(22).unwrap();
+        let counts = (vm.report().instructions, vm.report().back_edges_taken, vm.report().back_edges_not_taken);
+        assert_eq!(counts, (22, 2, 1), "{:?}", mode);
+        vm.run().unwrap();
+        let counts = (vm.report().instructions, vm.report().back_edges_taken, vm.report().back_edges_not_taken);
+        assert_eq!(counts, (1, 0, 0), "{:?}", mode);
+
+        match mode {
+            vm::RunningMode::Simple | vm::RunningMode::Replicated => {
+                assert_eq!(report.compile_time, std::time::Duration::ZERO, "{:?}", mode)
+            }
+            // Decoding nine bytes may take less than the resolution of the clock
+            vm::RunningMode::Decoded
+            | vm::RunningMode::Threaded
+            | vm::RunningMode::Closures
+            | vm::RunningMode::Superinstructions => (),
+            _ => assert!(report.compile_time > std::time::Duration::ZERO, "{:?}", mode),
+        }
+    }
+
+    let report = vm::VM::new(vm::RunningMode::Simple, prog).report();
+    let mut wtr = csv::Writer::from_writer(vec![]);
+    wtr.serialize(report).unwrap();
+    let csv = String::from_utf8(wtr.into_inner().unwrap()).unwrap();
+    assert_eq!(
+        csv.lines().next(),
+        Some("ip,acc,lc,halted,instructions,back_edges_taken,back_edges_not_taken,dispatches_saved,compile_ns,verify_ns,execute_ns")
+    );
+}
+
+#[test]
+pub fn compiled_program() {
+    let prog = generate_scenario(10_000, 1, [1, 9, 1, 5, 5]);
+    let registers = [(0, 0), (7, 0), (1, 3), (-5, 2)];
+
+    for mode in ALL_MODES {
+        let compiled = CompiledProgram::compile(&prog, mode.clone()).unwrap();
+
+        // Every run and every thread shares the same code
+        std::thread::scope(|scope| {
+            for (acc, lc) in registers {
+                let compiled = compiled.clone();
+                let prog = prog.clone();
+                let mode = mode.clone();
+                scope.spawn(move || {
+                    let mut expected = vm::VM::builder(prog).acc(acc).lc(lc).build();
+                    expected.run().unwrap();
+                    let report = compiled.run(acc, lc).unwrap();
+                    let expected = expected.report();
+                    assert_eq!(report.state(), expected.state(), "{:?}", mode);
+                    assert_eq!(report.instructions, expected.instructions, "{:?}", mode);
+                    assert_eq!(report.back_edges_taken, expected.back_edges_taken, "{:?}", mode);
+                    assert_eq!(report.back_edges_not_taken, expected.back_edges_not_taken, "{:?}", mode);
+                });
+            }
+        });
+
+        // VMs share the bytes of the program with the code instead of copying them
+        let mut vm = compiled.builder().acc(1).build();
+        assert!(std::ptr::eq(vm.program(), compiled.program()), "{:?}", mode);
+        vm.run().unwrap();
+        vm.reset();
+        vm.run().unwrap();
+        assert!(vm.is_halt());
+    }
+
+    // The overflow mode is part of the compiled code, the VM compiles again if it differs
+    let prog = Program::new(vec![4, 2, 2, 2, 2, 2, 2, 5, 0], i32::MAX, 0);
+    let compiled = CompiledProgram::compile(&prog, vm::RunningMode::OptJitted).unwrap();
+    let mut vm = compiled.builder().overflow_mode(vm::OverflowMode::Trap).build();
+    assert_eq!(vm.run(), Err(VmError::ArithmeticOverflow { ip: 1, acc: i32::MAX, lc: i32::MAX }));
+
+    // LLVM code compiled for unlimited runs does not check the fuel, the VM compiles again
+    let prog = Program::new(vec![4, 2, 2, 2, 2, 2, 2, 5, 0], 3, 0);
+    let compiled = CompiledProgram::compile(&prog, vm::RunningMode::OptJitted).unwrap();
+    let mut vm = compiled.builder().build();
+    assert_eq!(vm.run_with_fuel(8), Ok(vm::ExecutionOutcome::OutOfFuel { ip: 1, acc: 21, lc: 2 }));
+    vm.run().unwrap();
+    assert_eq!(vm.instructions(), 23);
+
+    assert!(CompiledProgram::compile(&Program::new(vec![2, 2], 0, 0), vm::RunningMode::OptJitted).is_err());
+}
+
 #[test]
 pub fn bench() {
 
-    let modes = [vm::RunningMode::Simple, vm::RunningMode::NoOptJitted, vm::RunningMode::OptJitted];
     let scenarios = [
         generate_scenario(10_000, 1, [0, 1, 0, 0, 0]),
         generate_scenario(10_000, 1, [1, 1, 1, 0, 0]),
@@ -118,18 +657,24 @@ pub fn bench() {
         generate_scenario(50_000, 1, [1, 9, 1, 5, 5])
     ];
 
-    let mut stats: Vec<Stats> = vec![];
+    // Each row is followed by the report of the last iteration
+    let mut stats: Vec<(Stats, ExecutionReport)> = vec![];
     let iterations = 32;
 
-    for mode in &modes {
+    for mode in ALL_MODES {
         println!("[info] :: running scenarios with mode '{:?}'", mode);
         for scenario_index in 0..scenarios.len() {
             
+            // Compile once, every iteration boots a VM sharing the code
+            let compiled = CompiledProgram::compile(&scenarios[scenario_index], mode.clone()).unwrap();
+
             let mut running_times = vec![];
+            let mut report = None;
             for _ in 0..iterations {
-                let vm = vm::VM::new(mode.clone(), scenarios[scenario_index].clone());
-                vm.run();
-                running_times.push(vm.running_time.get().as_nanos());
+                let mut vm = compiled.builder().build();
+                vm.run().unwrap();
+                running_times.push(vm.running_time.as_nanos());
+                report = Some(vm.report());
             }
 
             let min = running_times.clone().into_iter().min().unwrap();
@@ -137,14 +682,14 @@ pub fn bench() {
 
             let average = (running_times.into_iter().sum::<u128>() as f64) / (iterations as f64);
 
-            stats.push(Stats {
+            stats.push((Stats {
                 running_mode: mode.clone(),
                 scenario: (scenario_index + 1),
                 min: min,
                 max: max,
                 average,
                 iterations
-            })
+            }, report.unwrap()))
 
         }
     }
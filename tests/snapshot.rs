@@ -0,0 +1,100 @@
+use vt_vm::vm::{
+    self,
+    error::SnapshotError,
+    program::Program,
+    snapshot::Snapshot,
+    ExecutionOutcome, RunningMode, VM,
+};
+
+// SETL, INC3A x6, BACK7, CLRA, INC3A, SETL, INC3A x5, DECA, BACK7, HALT
+fn two_loops() -> Program {
+    Program::new(
+        vec![4, 2, 2, 2, 2, 2, 2, 5, 1, 2, 4, 2, 2, 2, 2, 2, 3, 5, 0],
+        5,
+        0,
+    )
+}
+
+#[test]
+pub fn snapshot_round_trip() {
+    let mut vm = VM::new(RunningMode::Simple, two_loops());
+    assert_eq!(vm.run_with_fuel(20), Ok(ExecutionOutcome::OutOfFuel { ip: 6, acc: 56, lc: 3 }));
+
+    let snapshot = vm.snapshot();
+    assert_eq!(snapshot.instructions, 20);
+    assert_eq!(snapshot.state, vm.state());
+    assert_eq!(Snapshot::from_bytes(&snapshot.to_bytes()).unwrap(), snapshot);
+    assert_eq!(Snapshot::from_json(&snapshot.to_json()).unwrap(), snapshot);
+
+    // Resume in a new VM, as another process would
+    let mut expected = VM::new(RunningMode::Simple, two_loops());
+    expected.run().unwrap();
+
+    let mut resumed = VM::new(RunningMode::Simple, two_loops());
+    resumed.restore(&Snapshot::from_bytes(&snapshot.to_bytes()).unwrap()).unwrap();
+    assert_eq!(resumed.run(), Ok(ExecutionOutcome::Halted));
+    assert_eq!(resumed.state(), expected.state());
+    assert_eq!(resumed.instructions(), expected.instructions());
+}
+
+#[test]
+pub fn snapshot_program_mismatch() {
+    let vm = VM::new(RunningMode::Simple, two_loops());
+    let snapshot = vm.snapshot();
+
+    let mut other = VM::new(RunningMode::Simple, Program::new(vec![2, 0], 0, 0));
+    assert!(matches!(
+        other.restore(&snapshot),
+        Err(SnapshotError::ProgramMismatch { .. })
+    ));
+
+    // The boot registers are not part of the fingerprint
+    let mut same_code = VM::builder(two_loops()).acc(1).build();
+    assert!(same_code.restore(&snapshot).is_ok());
+
+    let mut bad_ip = snapshot;
+    bad_ip.state.ip = 100;
+    assert!(matches!(
+        same_code.restore(&bad_ip),
+        Err(SnapshotError::IpOutOfBounds { ip: 100, len: 19 })
+    ));
+}
+
+#[test]
+pub fn snapshot_invalid_bytes() {
+    let bytes = VM::new(RunningMode::Simple, two_loops()).snapshot().to_bytes();
+
+    assert!(matches!(Snapshot::from_bytes(b"VTVM"), Err(SnapshotError::InvalidMagic)));
+    assert!(matches!(
+        Snapshot::from_bytes(&bytes[..20]),
+        Err(SnapshotError::Truncated { len: 20 })
+    ));
+
+    let mut newer = bytes.clone();
+    newer[4] = 2;
+    assert!(matches!(
+        Snapshot::from_bytes(&newer),
+        Err(SnapshotError::UnsupportedVersion { version: 2 })
+    ));
+
+    assert!(matches!(Snapshot::from_json("{}"), Err(SnapshotError::Json(_))));
+}
+
+#[test]
+pub fn snapshot_resume_jitted() {
+    let mut expected = VM::new(RunningMode::Simple, two_loops());
+    expected.run().unwrap();
+
+    // Pause at the header of the second loop, after its first iteration
+    let mut vm = VM::new(RunningMode::Simple, two_loops());
+    assert_eq!(vm.run_until(|vm| vm.ip() == 11 && vm.lc() == 2), Ok(vm::StepResult::Continue));
+    let snapshot = vm.snapshot();
+
+    for mode in [RunningMode::NoOptJitted, RunningMode::OptJitted] {
+        let mut resumed = vm::VM::new(mode.clone(), two_loops());
+        resumed.restore(&snapshot).unwrap();
+        assert_eq!(resumed.run(), Ok(ExecutionOutcome::Halted), "{:?}", mode);
+        assert_eq!(resumed.state(), expected.state(), "{:?}", mode);
+        assert_eq!(resumed.instructions(), expected.instructions(), "{:?}", mode);
+    }
+}